@@ -0,0 +1,181 @@
+use crate::move_loader::{MoveFileError, parse_tables};
+use crate::notation_parser::parse_button;
+use crate::static_types::{ATTACK_BUTTONS, AttackButton, ButtonNotation};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const PROFILE_DIR: &str = "profiles";
+
+// Game button each physical attack button performs, unmapped buttons are
+// ignored by move detection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ButtonProfile {
+    pub buttons: Vec<(AttackButton, ButtonNotation)>,
+}
+
+// Six button layout of a pad: punches on the top row, kicks on the bottom
+// row, the heavy ones on the right shoulder and trigger
+impl Default for ButtonProfile {
+    fn default() -> Self {
+        ButtonProfile {
+            buttons: vec![
+                (AttackButton::West, ButtonNotation::LP),
+                (AttackButton::North, ButtonNotation::MP),
+                (AttackButton::RightShoulder, ButtonNotation::HP),
+                (AttackButton::South, ButtonNotation::LK),
+                (AttackButton::East, ButtonNotation::MK),
+                (AttackButton::RightTrigger, ButtonNotation::HK),
+            ],
+        }
+    }
+}
+
+impl ButtonProfile {
+    pub fn game_button(&self, button: AttackButton) -> Option<ButtonNotation> {
+        self.buttons
+            .iter()
+            .find(|(physical, _)| *physical == button)
+            .map(|(_, game_button)| *game_button)
+    }
+
+    pub fn set(&mut self, button: AttackButton, game_button: ButtonNotation) {
+        self.buttons.retain(|(physical, _)| *physical != button);
+        self.buttons.push((button, game_button));
+    }
+}
+
+// `physical=GAME` as given to `--map`, e.g. `right_trigger=HK`
+pub fn parse_mapping(mapping: &str) -> Result<(AttackButton, ButtonNotation), String> {
+    let (physical, game) = mapping
+        .split_once('=')
+        .ok_or_else(|| format!("expected <button>=<game button>, got '{}'", mapping))?;
+    let physical = AttackButton::from_name(physical.trim()).ok_or_else(|| {
+        format!(
+            "unknown button '{}', expected one of {}",
+            physical.trim(),
+            ATTACK_BUTTONS.map(AttackButton::name).join(", ")
+        )
+    })?;
+    let game = parse_button(game.trim())
+        .ok_or_else(|| format!("unknown game button '{}'", game.trim()))?;
+    Ok((physical, game))
+}
+
+// Profiles are top level `physical = "GAME"` pairs, e.g. `west = "LP"`.
+// Buttons left out of the file are unmapped.
+pub fn parse_profile(contents: &str) -> Result<ButtonProfile, MoveFileError> {
+    let tables = parse_tables(contents)?;
+    if let Some(table) = tables.get(1) {
+        return Err(MoveFileError {
+            line: table.line,
+            message: "profiles only hold top level keys".to_string(),
+        });
+    }
+    let table = &tables[0];
+    table.check_keys(&ATTACK_BUTTONS.map(AttackButton::name))?;
+
+    let mut profile = ButtonProfile { buttons: vec![] };
+    for physical in ATTACK_BUTTONS {
+        let Some(name) = table.text(physical.name())? else {
+            continue;
+        };
+        let game_button = parse_button(&name).ok_or_else(|| MoveFileError {
+            line: table.line_of(physical.name()),
+            message: format!("unknown game button '{}'", name),
+        })?;
+        profile.set(physical, game_button);
+    }
+    Ok(profile)
+}
+
+pub fn format_profile(profile: &ButtonProfile, controller_name: &str) -> String {
+    let mut contents = format!("# Button profile for {}\n", controller_name);
+    for physical in ATTACK_BUTTONS {
+        if let Some(game_button) = profile.game_button(physical) {
+            contents.push_str(&format!("{} = \"{}\"\n", physical.name(), game_button));
+        }
+    }
+    contents
+}
+
+// Where the profile of a controller is kept, e.g.
+// "profiles/8bitdo_arcade_stick.toml"
+pub fn profile_path(controller_name: &str) -> PathBuf {
+    let mut file_name = String::new();
+    for c in controller_name.chars() {
+        if c.is_ascii_alphanumeric() {
+            file_name.push(c.to_ascii_lowercase());
+        } else if !file_name.is_empty() && !file_name.ends_with('_') {
+            file_name.push('_');
+        }
+    }
+    let file_name = file_name.trim_end_matches('_');
+    Path::new(PROFILE_DIR).join(format!("{}.toml", file_name))
+}
+
+pub fn load_profile(path: &Path) -> Result<ButtonProfile, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse_profile(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn save_profile(
+    path: &Path,
+    profile: &ButtonProfile,
+    controller_name: &str,
+) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, format_profile(profile, controller_name))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_round_trip() {
+        let mut profile = ButtonProfile::default();
+        profile.set(AttackButton::LeftTrigger, ButtonNotation::D);
+        profile.set(AttackButton::West, ButtonNotation::P);
+
+        let parsed = parse_profile(&format_profile(&profile, "Test Stick")).unwrap();
+        assert_eq!(
+            parsed.game_button(AttackButton::LeftTrigger),
+            Some(ButtonNotation::D)
+        );
+        assert_eq!(
+            parsed.game_button(AttackButton::West),
+            Some(ButtonNotation::P)
+        );
+        assert_eq!(parsed.game_button(AttackButton::LeftShoulder), None);
+        assert_eq!(parsed.buttons.len(), profile.buttons.len());
+    }
+
+    #[test]
+    fn test_profile_errors() {
+        assert_eq!(
+            parse_profile("west = \"LP\"\nnorth = \"XP\"")
+                .unwrap_err()
+                .to_string(),
+            "line 2: unknown game button 'XP'"
+        );
+        assert!(parse_profile("select = \"LP\"").is_err());
+        assert_eq!(
+            parse_mapping("right_trigger=hk"),
+            Ok((AttackButton::RightTrigger, ButtonNotation::HK))
+        );
+        assert!(parse_mapping("start=HK").is_err());
+    }
+
+    #[test]
+    fn test_profile_path() {
+        assert_eq!(
+            profile_path("8BitDo Arcade Stick (Xinput)"),
+            Path::new("profiles/8bitdo_arcade_stick_xinput.toml")
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use rust_input_speed::button_profile::parse_mapping;
+use rust_input_speed::keyboard_reader::{DEFAULT_ATTACK_KEYS, DIRECTION_KEYS};
+use rust_input_speed::player::PlayerSettings;
+use rust_input_speed::static_types::{
+    AttackButton, ButtonNotation, GATE_SHAPES, GateShape, HISTORY_MODES, HistoryMode,
+    LENIENCY_PRESETS, Leniency, SOCD_MODES, SocdMode, StickSettings,
+};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const USAGE: &str = "Usage: rust_input_speed [--moves <file.toml>] [--preset <game>] \
+[--history <frames>] [--negative-edge] [--input gamepad|keyboard] [--attack-keys <6 keys>] \
+[--replay <recording>] [--socd neutral|last|first|up] [--deadzone <0-1>] \
+[--diagonal-width <degrees>] [--gate square|octagon|circle] [--profile <file.toml>] \
+[--map <button>=<game button>]... [--save-profile] [--side-switch <seconds>] \
+[--history-mode game|lossless] [--headless] [--record <file>]";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputBackend {
+    Gamepad,
+    Keyboard,
+    // Recording of timestamped button events, played back frame by frame
+    Replay(PathBuf),
+}
+
+pub const DEFAULT_HISTORY_LENGTH: usize = 120;
+// An hour, the drill counts it in frames
+const MAX_SIDE_SWITCH: u64 = 3600;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppConfig {
+    // Move list to load instead of the built-in one
+    pub moves_path: Option<PathBuf>,
+    // Input buffers to use instead of the preset chosen by the move list
+    pub preset: Option<Leniency>,
+    // Number of frames of directions kept for move detection
+    pub history_length: usize,
+    // Also look for moves when an attack button is released
+    pub negative_edge: bool,
+    pub input: InputBackend,
+    // Keyboard keys for the attack buttons, top row then bottom row
+    pub attack_keys: [char; 6],
+    // What left+right and up+down resolve to
+    pub socd_mode: SocdMode,
+    pub stick: StickSettings,
+    // Button profile to use instead of the one saved for the controller
+    pub profile_path: Option<PathBuf>,
+    // Changes applied on top of the profile
+    pub mappings: Vec<(AttackButton, ButtonNotation)>,
+    // Write the resulting profile as the controller's profile
+    pub save_profile: bool,
+    // Drill where players switch sides every so many seconds
+    pub side_switch: Option<u64>,
+    // Whether directions between two frames reach the history
+    pub history_mode: HistoryMode,
+    // JSON lines on stdout instead of the terminal interface
+    pub headless: bool,
+    // Where to save the session as a replayable recording
+    pub record_path: Option<PathBuf>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            moves_path: None,
+            preset: None,
+            history_length: DEFAULT_HISTORY_LENGTH,
+            negative_edge: false,
+            input: InputBackend::Gamepad,
+            attack_keys: DEFAULT_ATTACK_KEYS,
+            socd_mode: SocdMode::Neutral,
+            stick: StickSettings::default(),
+            profile_path: None,
+            mappings: Vec::new(),
+            save_profile: false,
+            side_switch: None,
+            history_mode: HistoryMode::GameAccurate,
+            headless: false,
+            record_path: None,
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a number\n{}", flag, USAGE))?;
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got '{}'\n{}", flag, value, USAGE))
+}
+
+impl AppConfig {
+    // The part of the configuration each player reads its inputs with
+    pub fn player_settings(&self) -> PlayerSettings {
+        PlayerSettings {
+            socd_mode: self.socd_mode,
+            stick: self.stick,
+            history_mode: self.history_mode,
+        }
+    }
+
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<AppConfig, String> {
+        let mut config = AppConfig::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--moves" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| format!("--moves requires a file path\n{}", USAGE))?;
+                    config.moves_path = Some(PathBuf::from(path));
+                }
+                "--preset" => {
+                    let name = args
+                        .next()
+                        .ok_or_else(|| format!("--preset requires a game name\n{}", USAGE))?;
+                    let preset = Leniency::preset(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown preset '{}', expected one of {}",
+                            name,
+                            LENIENCY_PRESETS.map(|(name, _)| name).join(", ")
+                        )
+                    })?;
+                    config.preset = Some(preset);
+                }
+                "--negative-edge" => config.negative_edge = true,
+                "--headless" => config.headless = true,
+                "--input" => {
+                    config.input = match args.next().as_deref() {
+                        Some("gamepad") => InputBackend::Gamepad,
+                        Some("keyboard") => InputBackend::Keyboard,
+                        _ => {
+                            return Err(format!("--input expects gamepad or keyboard\n{}", USAGE));
+                        }
+                    }
+                }
+                "--attack-keys" => {
+                    let keys: Vec<char> = args
+                        .next()
+                        .unwrap_or_default()
+                        .to_lowercase()
+                        .chars()
+                        .collect();
+                    let keys: [char; 6] = keys
+                        .try_into()
+                        .map_err(|_| format!("--attack-keys expects exactly 6 keys\n{}", USAGE))?;
+                    if let Some(key) = keys.iter().find(|key| DIRECTION_KEYS.contains(key)) {
+                        return Err(format!(
+                            "--attack-keys cannot use '{}', WASD are the directions\n{}",
+                            key, USAGE
+                        ));
+                    }
+                    if let Some((_, key)) = keys
+                        .iter()
+                        .enumerate()
+                        .find(|&(idx, key)| keys[..idx].contains(key))
+                    {
+                        return Err(format!("--attack-keys has '{}' twice\n{}", key, USAGE));
+                    }
+                    config.attack_keys = keys;
+                }
+                "--profile" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| format!("--profile requires a file path\n{}", USAGE))?;
+                    config.profile_path = Some(PathBuf::from(path));
+                }
+                "--map" => {
+                    let mapping = args.next().unwrap_or_default();
+                    let mapping =
+                        parse_mapping(&mapping).map_err(|e| format!("--map: {}\n{}", e, USAGE))?;
+                    config.mappings.push(mapping);
+                }
+                "--save-profile" => config.save_profile = true,
+                "--side-switch" => {
+                    let seconds = parse_number("--side-switch", args.next())?;
+                    if !(1..=MAX_SIDE_SWITCH).contains(&seconds) {
+                        return Err(format!(
+                            "--side-switch expects 1 to {} seconds\n{}",
+                            MAX_SIDE_SWITCH, USAGE
+                        ));
+                    }
+                    config.side_switch = Some(seconds);
+                }
+                "--record" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| format!("--record requires a file path\n{}", USAGE))?;
+                    config.record_path = Some(PathBuf::from(path));
+                }
+                "--replay" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| format!("--replay requires a file path\n{}", USAGE))?;
+                    config.input = InputBackend::Replay(PathBuf::from(path));
+                }
+                "--socd" => {
+                    let name = args.next().unwrap_or_default();
+                    config.socd_mode = SocdMode::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown SOCD mode '{}', expected one of {}",
+                            name,
+                            SOCD_MODES.map(SocdMode::name).join(", ")
+                        )
+                    })?;
+                }
+                "--deadzone" => {
+                    let deadzone: f32 = parse_number("--deadzone", args.next())?;
+                    if !(0.0..1.0).contains(&deadzone) {
+                        return Err(format!("--deadzone expects a value from 0 to 1\n{}", USAGE));
+                    }
+                    config.stick.deadzone = deadzone;
+                }
+                "--diagonal-width" => {
+                    let width: f32 = parse_number("--diagonal-width", args.next())?;
+                    if !(0.0..=90.0).contains(&width) {
+                        return Err(format!(
+                            "--diagonal-width expects 0 to 90 degrees\n{}",
+                            USAGE
+                        ));
+                    }
+                    config.stick.diagonal_width = width;
+                }
+                "--gate" => {
+                    let name = args.next().unwrap_or_default();
+                    config.stick.gate = GateShape::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown gate '{}', expected one of {}",
+                            name,
+                            GATE_SHAPES.map(GateShape::name).join(", ")
+                        )
+                    })?;
+                }
+                "--history-mode" => {
+                    let name = args.next().unwrap_or_default();
+                    config.history_mode = HistoryMode::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown history mode '{}', expected one of {}",
+                            name,
+                            HISTORY_MODES.map(HistoryMode::name).join(", ")
+                        )
+                    })?;
+                }
+                "--history" => config.history_length = parse_number("--history", args.next())?,
+                _ => return Err(format!("Unknown argument '{}'\n{}", arg, USAGE)),
+            }
+        }
+
+        if config.record_path.is_some() && matches!(config.input, InputBackend::Replay(_)) {
+            return Err("--record saves live input, a replay is already recorded".to_string());
+        }
+        // Keys only reach the viewer through its terminal interface
+        if config.headless && config.input == InputBackend::Keyboard {
+            return Err("--headless needs a controller or --replay, not the keyboard".to_string());
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<AppConfig, String> {
+        AppConfig::from_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_attack_keys() {
+        let config = parse("--input keyboard --attack-keys QWERTY");
+        assert!(
+            config
+                .unwrap_err()
+                .starts_with("--attack-keys cannot use 'w'")
+        );
+        assert!(
+            parse("--attack-keys uiojkk")
+                .unwrap_err()
+                .starts_with("--attack-keys has 'k' twice")
+        );
+        assert!(parse("--attack-keys uiojk").is_err());
+        assert_eq!(
+            parse("--attack-keys 123456").unwrap().attack_keys,
+            ['1', '2', '3', '4', '5', '6']
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let config = parse(
+            "--moves moves.toml --preset sf6 --history 200 --negative-edge --input keyboard \
+             --socd last --deadzone 0.3 --diagonal-width 30 --gate square --profile pad.toml \
+             --map right_trigger=HK --save-profile --side-switch 10 --history-mode lossless \
+             --record session.txt",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            AppConfig {
+                moves_path: Some(PathBuf::from("moves.toml")),
+                preset: Leniency::preset("sf6"),
+                history_length: 200,
+                negative_edge: true,
+                input: InputBackend::Keyboard,
+                attack_keys: DEFAULT_ATTACK_KEYS,
+                socd_mode: SocdMode::LastInput,
+                stick: StickSettings {
+                    deadzone: 0.3,
+                    diagonal_width: 30.0,
+                    gate: GateShape::Square,
+                },
+                profile_path: Some(PathBuf::from("pad.toml")),
+                mappings: vec![(AttackButton::RightTrigger, ButtonNotation::HK)],
+                save_profile: true,
+                side_switch: Some(10),
+                history_mode: HistoryMode::Lossless,
+                headless: false,
+                record_path: Some(PathBuf::from("session.txt")),
+            }
+        );
+        assert_eq!(parse("").unwrap(), AppConfig::default());
+        assert_eq!(
+            parse("--headless --replay session.txt").unwrap().input,
+            InputBackend::Replay(PathBuf::from("session.txt"))
+        );
+
+        assert!(
+            parse("--jump")
+                .unwrap_err()
+                .starts_with("Unknown argument '--jump'")
+        );
+        assert!(
+            parse("--socd sideways")
+                .unwrap_err()
+                .starts_with("Unknown SOCD mode")
+        );
+        assert!(parse("--deadzone 1").is_err());
+        assert!(parse("--diagonal-width 91").is_err());
+        assert!(parse("--history lots").is_err());
+        assert!(parse("--record").is_err());
+    }
+
+    #[test]
+    fn test_side_switch_bounds() {
+        assert_eq!(parse("--side-switch 1").unwrap().side_switch, Some(1));
+        assert_eq!(
+            parse("--side-switch 3600").unwrap().side_switch,
+            Some(MAX_SIDE_SWITCH)
+        );
+        for seconds in ["0", "3601", "-5", "soon"] {
+            assert!(
+                parse(&format!("--side-switch {}", seconds)).is_err(),
+                "{}",
+                seconds
+            );
+        }
+    }
+
+    #[test]
+    fn test_conflicting_flags() {
+        assert_eq!(
+            parse("--replay session.txt --record copy.txt").unwrap_err(),
+            "--record saves live input, a replay is already recorded"
+        );
+        assert_eq!(
+            parse("--headless --input keyboard").unwrap_err(),
+            "--headless needs a controller or --replay, not the keyboard"
+        );
+        assert!(parse("--headless").is_ok());
+    }
+}
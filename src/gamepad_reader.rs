@@ -0,0 +1,91 @@
+use crate::input_source::InputSource;
+use crate::static_types::{
+    AttackButton, ButtonState, InputButton, InputChange, InputEvent, StickAxis,
+};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use std::time::{Instant, SystemTime};
+
+pub struct GamepadSource {
+    gilrs: Gilrs,
+    started: SystemTime,
+    // Gamepads in the order they first sent an event, indexed by device
+    devices: Vec<GamepadId>,
+}
+
+impl GamepadSource {
+    // Event times count from `started`, the instant frame 0 starts at;
+    // gilrs stamps events with the system clock
+    pub fn new(started: Instant) -> Result<GamepadSource, String> {
+        let gilrs = Gilrs::new().map_err(|e| format!("Failed to open gamepads: {}", e))?;
+        let now = SystemTime::now();
+        Ok(GamepadSource {
+            gilrs,
+            started: now.checked_sub(started.elapsed()).unwrap_or(now),
+            devices: Vec::new(),
+        })
+    }
+
+    fn device(&mut self, id: GamepadId) -> usize {
+        match self.devices.iter().position(|&device_id| device_id == id) {
+            Some(device) => device,
+            None => {
+                self.devices.push(id);
+                self.devices.len() - 1
+            }
+        }
+    }
+}
+
+fn gamepad_button(button: Button) -> Option<InputButton> {
+    match button {
+        Button::DPadUp => Some(InputButton::Up),
+        Button::DPadDown => Some(InputButton::Down),
+        Button::DPadLeft => Some(InputButton::Left),
+        Button::DPadRight => Some(InputButton::Right),
+        Button::North => Some(InputButton::Attack(AttackButton::North)),
+        Button::South => Some(InputButton::Attack(AttackButton::South)),
+        Button::East => Some(InputButton::Attack(AttackButton::East)),
+        Button::West => Some(InputButton::Attack(AttackButton::West)),
+        // gilrs calls the shoulder buttons triggers and the triggers "2"
+        Button::LeftTrigger => Some(InputButton::Attack(AttackButton::LeftShoulder)),
+        Button::RightTrigger => Some(InputButton::Attack(AttackButton::RightShoulder)),
+        Button::LeftTrigger2 => Some(InputButton::Attack(AttackButton::LeftTrigger)),
+        Button::RightTrigger2 => Some(InputButton::Attack(AttackButton::RightTrigger)),
+        _ => None,
+    }
+}
+
+impl InputSource for GamepadSource {
+    fn controller_name(&self, device: usize) -> Option<String> {
+        let gamepad = self.gilrs.connected_gamepad(*self.devices.get(device)?)?;
+        Some(gamepad.name().to_string())
+    }
+
+    fn poll(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            let change = match event.event {
+                EventType::ButtonPressed(button, _) => gamepad_button(button)
+                    .map(|button| InputChange::Button(button, ButtonState::Pressed)),
+                EventType::ButtonReleased(button, _) => gamepad_button(button)
+                    .map(|button| InputChange::Button(button, ButtonState::Released)),
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => {
+                    Some(InputChange::Stick(StickAxis::X, value))
+                }
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+                    Some(InputChange::Stick(StickAxis::Y, value))
+                }
+                _ => None,
+            };
+            let Some(change) = change else {
+                continue;
+            };
+            events.push(InputEvent {
+                time: event.time.duration_since(self.started).unwrap_or_default(),
+                device: self.device(event.id),
+                change,
+            });
+        }
+        events
+    }
+}
@@ -0,0 +1,203 @@
+use crate::player::FrameReport;
+use crate::static_types::{
+    ButtonEdge, ButtonNotation, Diagnosis, Edge, FRAME_DURATION, MatchResult,
+};
+use std::time::Duration;
+
+// Bumped whenever a field changes meaning or goes away; new fields may be
+// added within a version
+pub const SCHEMA_VERSION: u32 = 1;
+
+// First line of the stream
+pub fn header_line() -> String {
+    format!(
+        "{{\"schema\":{},\"type\":\"header\",\"frame_ms\":{}}}",
+        SCHEMA_VERSION,
+        milliseconds(FRAME_DURATION)
+    )
+}
+
+// One JSON object per line for each change of a player's frame, in the order
+// direction changes, button edges, detected move or failed attempt
+pub fn report_lines(player: usize, frame: u64, report: &FrameReport) -> Vec<String> {
+    let prefix = |kind: &str| {
+        format!(
+            "{{\"schema\":{},\"type\":\"{}\",\"player\":{},\"frame\":{}",
+            SCHEMA_VERSION, kind, player, frame
+        )
+    };
+    let mut lines = Vec::new();
+    for change in &report.directions {
+        lines.push(format!(
+            "{},\"direction\":{},\"as_1p\":{},\"time_ms\":{}}}",
+            prefix("direction"),
+            change.direction as u8,
+            change.as_1p as u8,
+            milliseconds(change.time)
+        ));
+    }
+    for (button_edge, game_button) in &report.button_edges {
+        lines.push(format!(
+            "{},{}}}",
+            prefix("button"),
+            button_fields(button_edge, *game_button)
+        ));
+    }
+    if let Some(match_result) = &report.detected {
+        lines.push(format!(
+            "{},{}}}",
+            prefix("move"),
+            move_fields(match_result)
+        ));
+    }
+    if let Some(diagnosis) = &report.missed {
+        lines.push(format!("{},{}}}", prefix("miss"), miss_fields(diagnosis)));
+    }
+    lines
+}
+
+fn button_fields(button_edge: &ButtonEdge, game_button: Option<ButtonNotation>) -> String {
+    format!(
+        "\"button\":{},\"game_button\":{},\"edge\":\"{}\"",
+        json_string(button_edge.button.name()),
+        optional(game_button.map(|game_button| json_string(&game_button.to_string()))),
+        edge_name(button_edge.edge)
+    )
+}
+
+fn move_fields(match_result: &MatchResult) -> String {
+    let steps = match_result
+        .steps
+        .iter()
+        .map(|step| (step.direction as u8).to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let step_gaps = match_result
+        .step_gaps
+        .iter()
+        .map(|gap| gap.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let real_timings = &match_result.real_timings;
+    format!(
+        "\"move\":{},\"button\":{},\"edge\":\"{}\",\"steps\":[{}],\"step_gaps\":[{}],\
+\"charge_frames\":{},\"button_delay\":{},\"total_frames\":{},\"motion_ms\":{},\
+\"button_delay_ms\":{}",
+        json_string(&match_result.move_id.0),
+        json_string(&match_result.button.to_string()),
+        edge_name(match_result.edge),
+        steps,
+        step_gaps,
+        optional(match_result.charge_frames.map(|frames| frames.to_string())),
+        match_result.button_delay,
+        match_result.total_frames,
+        optional(
+            real_timings
+                .as_ref()
+                .map(|timings| milliseconds(timings.motion))
+        ),
+        optional(
+            real_timings
+                .as_ref()
+                .map(|timings| milliseconds(timings.button_delay))
+        )
+    )
+}
+
+fn miss_fields(diagnosis: &Diagnosis) -> String {
+    format!(
+        "\"move\":{},\"reason\":{}",
+        json_string(&diagnosis.move_id.0),
+        json_string(&diagnosis.reason.to_string())
+    )
+}
+
+fn edge_name(edge: Edge) -> &'static str {
+    match edge {
+        Edge::Press => "press",
+        Edge::Release => "release",
+    }
+}
+
+fn optional(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "null".to_string())
+}
+
+// Milliseconds as a JSON number, to the microsecond
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::button_profile::ButtonProfile;
+    use crate::player::{Player, PlayerSettings, play_recording};
+
+    // Lines of every frame of a recording played by player 1
+    fn run(recording: &str, frames: u64) -> Vec<String> {
+        let settings = PlayerSettings::default();
+        let mut players = [Player::new(1, 0, ButtonProfile::default(), &settings)];
+        let mut lines = Vec::new();
+        play_recording(
+            recording,
+            &mut players,
+            &settings,
+            frames,
+            |frame, players| {
+                lines.extend(report_lines(1, frame, &players[0].report));
+            },
+        );
+        lines
+    }
+
+    #[test]
+    fn test_schema_v1_lines() {
+        assert_eq!(
+            header_line(),
+            "{\"schema\":1,\"type\":\"header\",\"frame_ms\":16.667}"
+        );
+        let lines = run(
+            "0 right press\n20 right release\n20 down press\n40 right press\n45 west press",
+            3,
+        );
+        assert_eq!(
+            lines,
+            vec![
+                "{\"schema\":1,\"type\":\"direction\",\"player\":1,\"frame\":0,\"direction\":6,\"as_1p\":6,\"time_ms\":0.000}",
+                "{\"schema\":1,\"type\":\"direction\",\"player\":1,\"frame\":1,\"direction\":2,\"as_1p\":2,\"time_ms\":20.000}",
+                "{\"schema\":1,\"type\":\"direction\",\"player\":1,\"frame\":2,\"direction\":3,\"as_1p\":3,\"time_ms\":40.000}",
+                "{\"schema\":1,\"type\":\"button\",\"player\":1,\"frame\":2,\"button\":\"west\",\"game_button\":\"LP\",\"edge\":\"press\"}",
+                "{\"schema\":1,\"type\":\"move\",\"player\":1,\"frame\":2,\"move\":\"DP\",\"button\":\"LP\",\"edge\":\"press\",\"steps\":[6,2,3],\"step_gaps\":[1,1],\"charge_frames\":null,\"button_delay\":0,\"total_frames\":3,\"motion_ms\":45.000,\"button_delay_ms\":5.000}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_v1_misses_and_escapes() {
+        // 6-3 + LP, a DP missing its 2
+        let lines = run("0 right press\n20 down press\n40 west press", 3);
+        assert!(
+            lines
+                .last()
+                .unwrap()
+                .starts_with("{\"schema\":1,\"type\":\"miss\",\"player\":1,\"frame\":2,\"move\":")
+        );
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+    }
+}
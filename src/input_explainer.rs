@@ -1,18 +1,38 @@
 use crate::static_types::{
-    ButtonState, ButtonsStates, GlobalState, Moves, NumericalNotation, create_move_map,
+    ButtonNotation, Diagnosis, Edge, FailureReason, MatchResult, MatchedStep, MoveDefinition,
+    MoveId, NumericalNotation,
 };
+use std::cmp::Reverse;
 use std::collections::HashMap;
 
-fn is_valid_sequence(
+// Frames between two history entries. A lossless history can hold several
+// entries for one frame, so the indexes alone do not tell.
+fn frames_between(history_frames: &[u64], from_idx: usize, to_idx: usize) -> usize {
+    (history_frames[to_idx] - history_frames[from_idx]) as usize
+}
+
+// First history index at most `frames` frames before the last entry
+fn first_within(history_frames: &[u64], frames: usize) -> usize {
+    let Some(&last_frame) = history_frames.last() else {
+        return 0;
+    };
+    history_frames.partition_point(|&frame| (last_frame - frame) as usize > frames)
+}
+
+// Matches the motion against the history from the most recent input
+// backwards and returns the history index of every step, oldest first
+fn find_sequence(
     position_history: &[NumericalNotation],
+    history_frames: &[u64],
     move_sequence: &[NumericalNotation],
-) -> bool {
+    step_gap: usize,
+) -> Option<Vec<usize>> {
     if move_sequence.is_empty() {
-        return true;
+        return Some(Vec::new());
     }
 
     if position_history.is_empty() {
-        return false;
+        return None;
     }
 
     let mut history_idx = position_history.len() - 1;
@@ -26,18 +46,30 @@ fn is_valid_sequence(
             if sequence_idx == 0 {
                 first_occurrences.reverse();
                 for i in 1..first_occurrences.len() {
-                    let distance = first_occurrences[i] - first_occurrences[i - 1];
-                    if distance > 7 {
-                        return false;
+                    let distance = frames_between(
+                        history_frames,
+                        first_occurrences[i - 1],
+                        first_occurrences[i],
+                    );
+                    if distance > step_gap {
+                        return None;
                     }
                 }
-                return true;
+                return Some(first_occurrences);
             }
             sequence_idx -= 1;
+
+            // The previous step has to come from an earlier press, not from
+            // holding this one, so "22" needs the stick to leave 2 in between
+            while history_idx > 0
+                && position_history[history_idx - 1] == position_history[history_idx]
+            {
+                history_idx -= 1;
+            }
         }
 
         if history_idx == 0 {
-            return false;
+            return None;
         }
 
         // Move backwards in history
@@ -45,89 +77,587 @@ fn is_valid_sequence(
     }
 }
 
-fn count_distance(
+// Checks that `charge` was held for at least `charge_frames` frames and let
+// go no more than `step_gap` frames before `release_idx`. Diagonals count
+// towards both of their directions. Returns where the charge started and
+// how many frames it was held.
+fn find_charge_start(
     position_history: &[NumericalNotation],
-    move_sequence: &[NumericalNotation],
-) -> usize {
-    if move_sequence.is_empty() || position_history.is_empty() {
-        return 0;
+    history_frames: &[u64],
+    charge: NumericalNotation,
+    charge_frames: usize,
+    release_idx: usize,
+    step_gap: usize,
+) -> Option<(usize, usize)> {
+    let mut history_idx = release_idx;
+    while history_idx > 0 && !position_history[history_idx - 1].includes(charge) {
+        history_idx -= 1;
+        if frames_between(history_frames, history_idx, release_idx) > step_gap {
+            return None;
+        }
     }
 
-    let first_input = move_sequence.first().unwrap();
-    let last_input = move_sequence.last().unwrap();
+    let charge_end = history_idx;
+    while history_idx > 0 && position_history[history_idx - 1].includes(charge) {
+        history_idx -= 1;
+    }
 
-    let mut first_input_index = None;
-    for (i, position) in position_history.iter().enumerate().rev() {
-        if position == first_input {
-            first_input_index = Some(i);
-            break;
-        }
+    let held_frames = frames_between(history_frames, history_idx, charge_end);
+    if held_frames >= charge_frames {
+        Some((history_idx, held_frames))
+    } else {
+        None
+    }
+}
+
+// Position of a cardinal direction around the stick, counter-clockwise
+fn rotation_slot(position: NumericalNotation) -> Option<usize> {
+    match position {
+        NumericalNotation::Six => Some(0),
+        NumericalNotation::Eight => Some(1),
+        NumericalNotation::Four => Some(2),
+        NumericalNotation::Two => Some(3),
+        _ => None,
     }
+}
 
-    let mut last_input_index = None;
+// Looks for `rotations` full turns of the stick ending at the end of the
+// history, starting anywhere and going either way round. Diagonals are
+// skipped, going through neutral breaks the rotation. Returns the history
+// index of every cardinal direction of the rotation, oldest first.
+fn find_rotation(
+    position_history: &[NumericalNotation],
+    history_frames: &[u64],
+    rotations: usize,
+    step_gap: usize,
+) -> Option<Vec<usize>> {
+    let needed_cardinals = rotations * 4;
+    let mut last_cardinal: Option<(usize, usize)> = None;
+    let mut turn_direction = None;
+    let mut cardinal_indexes = Vec::new();
 
-    if let Some(first_idx) = first_input_index {
-        for (i, position) in position_history[first_idx..].iter().enumerate().rev() {
-            if position == last_input {
-                last_input_index = Some(first_idx + i);
-                break;
-            }
+    for (history_idx, position) in position_history.iter().enumerate().rev() {
+        if *position == NumericalNotation::Five {
+            break;
         }
-    } else {
-        for (i, position) in position_history.iter().enumerate().rev() {
-            if position == last_input {
-                last_input_index = Some(i);
-                break;
+        let Some(slot) = rotation_slot(*position) else {
+            continue;
+        };
+
+        match last_cardinal {
+            None => cardinal_indexes.push(history_idx),
+            Some((previous_slot, _)) if previous_slot == slot => {}
+            Some((previous_slot, previous_idx)) => {
+                let step = (previous_slot + 4 - slot) % 4;
+                if step == 2
+                    || turn_direction.is_some_and(|direction| direction != step)
+                    || frames_between(history_frames, history_idx, previous_idx) > step_gap
+                {
+                    break;
+                }
+                turn_direction = Some(step);
+                cardinal_indexes.push(history_idx);
             }
         }
+        last_cardinal = Some((slot, history_idx));
+
+        if cardinal_indexes.len() == needed_cardinals {
+            cardinal_indexes.reverse();
+            return Some(cardinal_indexes);
+        }
     }
 
-    match (first_input_index, last_input_index) {
-        (Some(first_idx), Some(last_idx)) => {
-            if last_idx >= first_idx {
-                last_idx - first_idx + 1
-            } else {
-                0
-            }
+    None
+}
+
+// Orders matching moves so the preferred one compares greatest:
+// explicit priority first, then the longest motion, then the name so the
+// result never depends on HashMap iteration order
+fn match_rank<'a>(
+    move_name: &'a MoveId,
+    move_definition: &MoveDefinition,
+) -> (usize, usize, Reverse<&'a str>) {
+    (
+        move_definition.priority,
+        move_definition.motion_length(),
+        Reverse(move_name.0.as_str()),
+    )
+}
+
+// Latest history index where the motion can have ended: the last frame
+// matching `ends_motion`, at most `button_window` frames before the button
+fn motion_end(
+    position_history: &[NumericalNotation],
+    history_frames: &[u64],
+    button_window: usize,
+    ends_motion: impl Fn(NumericalNotation) -> bool,
+) -> Option<usize> {
+    let last_idx = position_history.len().checked_sub(1)?;
+    (first_within(history_frames, button_window)..=last_idx)
+        .rev()
+        .find(|&history_idx| ends_motion(position_history[history_idx]))
+}
+
+// Where a move was found in the history
+struct MoveMatch {
+    steps: Vec<usize>,
+    // Start of the charge and how long it was held
+    charge: Option<(usize, usize)>,
+}
+
+fn within_motion_window(history_frames: &[u64], steps: &[usize], motion_window: usize) -> bool {
+    match (steps.first(), steps.last()) {
+        (Some(&first_idx), Some(&last_idx)) => {
+            frames_between(history_frames, first_idx, last_idx) <= motion_window
+        }
+        _ => false,
+    }
+}
+
+fn match_move(
+    position_history: &[NumericalNotation],
+    history_frames: &[u64],
+    move_definition: &MoveDefinition,
+) -> Option<MoveMatch> {
+    let leniency = move_definition.leniency;
+
+    if move_definition.rotations > 0 {
+        let end_idx = motion_end(
+            position_history,
+            history_frames,
+            leniency.button_window,
+            |position| rotation_slot(position).is_some(),
+        )?;
+        let steps = find_rotation(
+            &position_history[..=end_idx],
+            &history_frames[..=end_idx],
+            move_definition.rotations,
+            leniency.step_gap,
+        )?;
+        return within_motion_window(history_frames, &steps, leniency.motion_window).then_some(
+            MoveMatch {
+                steps,
+                charge: None,
+            },
+        );
+    }
+
+    let last_direction = *move_definition.directions.last()?;
+    let end_idx = motion_end(
+        position_history,
+        history_frames,
+        leniency.button_window,
+        |position| position == last_direction,
+    )?;
+    let position_history = &position_history[..=end_idx];
+    let history_frames = &history_frames[..=end_idx];
+
+    if let Some(charge) = move_definition.charge {
+        let steps = find_sequence(
+            position_history,
+            history_frames,
+            &move_definition.directions,
+            leniency.step_gap,
+        )?;
+        if !within_motion_window(history_frames, &steps, leniency.motion_window) {
+            return None;
         }
-        _ => 0,
+        let charge = find_charge_start(
+            position_history,
+            history_frames,
+            charge,
+            move_definition.charge_frames,
+            steps[0],
+            leniency.step_gap,
+        )?;
+        return Some(MoveMatch {
+            steps,
+            charge: Some(charge),
+        });
     }
+
+    let steps = find_sequence(
+        position_history,
+        history_frames,
+        &move_definition.sequence(),
+        leniency.step_gap,
+    )?;
+    within_motion_window(history_frames, &steps, leniency.motion_window).then_some(MoveMatch {
+        steps,
+        charge: None,
+    })
 }
 
+fn build_result(
+    position_history: &[NumericalNotation],
+    history_frames: &[u64],
+    move_name: &MoveId,
+    move_match: MoveMatch,
+    button: ButtonNotation,
+    edge: Edge,
+) -> MatchResult {
+    let last_idx = position_history.len() - 1;
+    let first_step = move_match.steps[0];
+    let last_step = move_match.steps[move_match.steps.len() - 1];
+    let motion_start = move_match
+        .charge
+        .map_or(first_step, |(charge_start, _)| charge_start);
+
+    MatchResult {
+        move_id: move_name.clone(),
+        steps: move_match
+            .steps
+            .iter()
+            .map(|&history_index| MatchedStep {
+                direction: position_history[history_index],
+                history_index,
+            })
+            .collect(),
+        step_gaps: move_match
+            .steps
+            .windows(2)
+            .map(|pair| frames_between(history_frames, pair[0], pair[1]))
+            .collect(),
+        charge_frames: move_match.charge.map(|(_, held_frames)| held_frames),
+        button_delay: frames_between(history_frames, last_step, last_idx),
+        total_frames: frames_between(history_frames, motion_start, last_idx) + 1,
+        button,
+        edge,
+        real_timings: None,
+    }
+}
+
+// Shortest history that can still contain every move of the list, charge
+// moves being the ones that need to look furthest back
+pub fn required_history_length(move_map: &HashMap<MoveId, MoveDefinition>) -> usize {
+    move_map
+        .values()
+        .map(|move_definition| {
+            let leniency = move_definition.leniency;
+            move_definition.charge_frames
+                + leniency.step_gap
+                + leniency.motion_window
+                + leniency.button_window
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// First of the game buttons `buttons` that performs the move
+fn performing_button(
+    move_definition: &MoveDefinition,
+    buttons: &[ButtonNotation],
+) -> Option<ButtonNotation> {
+    buttons
+        .iter()
+        .copied()
+        .find(|&button| move_definition.button.matches(button))
+}
+
+// Finds the move performed by the game `buttons` changing with `edge`; on a
+// release only moves accepting negative edge are considered.
+// `history_frames` holds the game frame of every history entry.
 pub fn check_move_sequence(
     position_history: &[NumericalNotation],
-    move_map: &HashMap<Moves, Vec<NumericalNotation>>,
-) -> (Option<Moves>, usize) {
-    for (move_name, move_sequence) in move_map {
-        if move_sequence.last() == position_history.last() {
-            if is_valid_sequence(position_history, move_sequence) {
-                return (
-                    Some(*move_name),
-                    count_distance(position_history, move_sequence),
-                );
+    history_frames: &[u64],
+    move_map: &HashMap<MoveId, MoveDefinition>,
+    buttons: &[ButtonNotation],
+    edge: Edge,
+) -> Option<MatchResult> {
+    move_map
+        .iter()
+        .filter(|(_, move_definition)| edge == Edge::Press || move_definition.negative_edge)
+        .filter_map(|(move_name, move_definition)| {
+            let button = performing_button(move_definition, buttons)?;
+            match_move(position_history, history_frames, move_definition)
+                .map(|move_match| (move_name, move_definition, move_match, button))
+        })
+        .max_by_key(|(move_name, move_definition, _, _)| match_rank(move_name, move_definition))
+        .map(|(move_name, _, move_match, button)| {
+            build_result(
+                position_history,
+                history_frames,
+                move_name,
+                move_match,
+                button,
+                edge,
+            )
+        })
+}
+
+// How far back a failed attempt is looked for: twice the time the move
+// allows, so slow attempts are still recognised as attempts
+fn attempt_window(move_definition: &MoveDefinition) -> usize {
+    let leniency = move_definition.leniency;
+    2 * (leniency.step_gap + leniency.motion_window + leniency.button_window)
+        + move_definition.charge_frames
+}
+
+// The motion was performed in the right order, find which window it missed
+fn diagnose_timing(
+    position_history: &[NumericalNotation],
+    history_frames: &[u64],
+    move_definition: &MoveDefinition,
+) -> Option<FailureReason> {
+    let leniency = move_definition.leniency;
+    let last_idx = position_history.len().checked_sub(1)?;
+    let window = attempt_window(move_definition);
+    let oldest_idx = first_within(history_frames, window);
+    let last_direction = *move_definition.directions.last()?;
+
+    let end_idx = motion_end(position_history, history_frames, window, |position| {
+        position == last_direction
+    })?;
+    let steps = find_sequence(
+        &position_history[..=end_idx],
+        &history_frames[..=end_idx],
+        &move_definition.directions,
+        usize::MAX,
+    )?;
+    if steps[0] < oldest_idx {
+        return None;
+    }
+    // Without the charge, or with a single direction, the button was more
+    // likely a normal than a late special
+    let held = match move_definition.charge {
+        Some(charge) => {
+            let held = find_charge_start(
+                &position_history[..=end_idx],
+                &history_frames[..=end_idx],
+                charge,
+                0,
+                steps[0],
+                leniency.step_gap,
+            )
+            .map_or(0, |(_, held_frames)| held_frames);
+            if held == 0 {
+                return None;
             }
+            held
         }
+        None if steps.len() < 2 => return None,
+        None => 0,
+    };
+
+    let button_delay = frames_between(history_frames, end_idx, last_idx);
+    if button_delay > leniency.button_window {
+        return Some(FailureReason::ButtonLate {
+            late_by: button_delay - leniency.button_window,
+        });
+    }
+
+    if let Some(pair) = steps
+        .windows(2)
+        .find(|pair| frames_between(history_frames, pair[0], pair[1]) > leniency.step_gap)
+    {
+        return Some(FailureReason::StepTooSlow {
+            from: position_history[pair[0]],
+            to: position_history[pair[1]],
+            gap: frames_between(history_frames, pair[0], pair[1]),
+            limit: leniency.step_gap,
+        });
+    }
+
+    let motion_frames = frames_between(history_frames, steps[0], steps[steps.len() - 1]);
+    if motion_frames > leniency.motion_window {
+        return Some(FailureReason::MotionTooSlow {
+            frames: motion_frames,
+            limit: leniency.motion_window,
+        });
     }
-    (None, 0)
+
+    move_definition.charge?;
+    (held < move_definition.charge_frames).then_some(FailureReason::ChargeTooShort {
+        held,
+        needed: move_definition.charge_frames,
+    })
+}
+
+// Longest common subsequence between the motion and the directions
+// recently entered, to tell which steps were skipped
+fn diagnose_missing_steps(
+    position_history: &[NumericalNotation],
+    history_frames: &[u64],
+    move_definition: &MoveDefinition,
+) -> Option<FailureReason> {
+    let sequence = move_definition.sequence();
+    let oldest_idx = first_within(history_frames, attempt_window(move_definition));
+    let mut entered: Vec<NumericalNotation> = Vec::new();
+    for position in &position_history[oldest_idx..] {
+        if entered.last() != Some(position) {
+            entered.push(*position);
+        }
+    }
+
+    let mut lengths = vec![vec![0usize; sequence.len() + 1]; entered.len() + 1];
+    for entered_idx in 1..=entered.len() {
+        for sequence_idx in 1..=sequence.len() {
+            lengths[entered_idx][sequence_idx] = if entered[entered_idx - 1]
+                == sequence[sequence_idx - 1]
+            {
+                lengths[entered_idx - 1][sequence_idx - 1] + 1
+            } else {
+                lengths[entered_idx - 1][sequence_idx].max(lengths[entered_idx][sequence_idx - 1])
+            };
+        }
+    }
+
+    let mut is_matched = vec![false; sequence.len()];
+    let (mut entered_idx, mut sequence_idx) = (entered.len(), sequence.len());
+    while entered_idx > 0 && sequence_idx > 0 {
+        if entered[entered_idx - 1] == sequence[sequence_idx - 1] {
+            is_matched[sequence_idx - 1] = true;
+            entered_idx -= 1;
+            sequence_idx -= 1;
+        } else if lengths[entered_idx - 1][sequence_idx] >= lengths[entered_idx][sequence_idx - 1] {
+            entered_idx -= 1;
+        } else {
+            sequence_idx -= 1;
+        }
+    }
+
+    let matched_count = is_matched.iter().filter(|matched| **matched).count();
+    // A single direction or less than half the motion is more likely a
+    // different move or no attempt at all
+    if matched_count < 2 || matched_count == sequence.len() || matched_count * 2 < sequence.len() {
+        return None;
+    }
+
+    let (matched, missing): (Vec<_>, Vec<_>) = sequence
+        .iter()
+        .zip(is_matched)
+        .partition(|(_, matched)| *matched);
+    Some(FailureReason::MissingSteps {
+        matched: matched
+            .into_iter()
+            .map(|(direction, _)| *direction)
+            .collect(),
+        missing: missing
+            .into_iter()
+            .map(|(direction, _)| *direction)
+            .collect(),
+    })
+}
+
+// Explains why the button press did not give a move: picks the move the
+// player most likely went for and what went wrong with it. Attempts that
+// only missed a timing window are closer than ones missing directions.
+// Only moves of the pressed `buttons` are candidates, rotation moves are not
+// diagnosed.
+pub fn diagnose_attempt(
+    position_history: &[NumericalNotation],
+    history_frames: &[u64],
+    move_map: &HashMap<MoveId, MoveDefinition>,
+    buttons: &[ButtonNotation],
+) -> Option<Diagnosis> {
+    move_map
+        .iter()
+        .filter(|(_, move_definition)| {
+            move_definition.rotations == 0 && performing_button(move_definition, buttons).is_some()
+        })
+        .filter_map(|(move_name, move_definition)| {
+            let reason = diagnose_timing(position_history, history_frames, move_definition)
+                .or_else(|| {
+                    diagnose_missing_steps(position_history, history_frames, move_definition)
+                })?;
+            let closeness = match &reason {
+                FailureReason::MissingSteps { matched, missing } => {
+                    (0, matched.len() * 100 / (matched.len() + missing.len()))
+                }
+                _ => (1, 100),
+            };
+            Some((
+                closeness,
+                match_rank(move_name, move_definition),
+                move_name,
+                reason,
+            ))
+        })
+        .max_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)))
+        .map(|(_, _, move_name, reason)| Diagnosis {
+            move_id: move_name.clone(),
+            reason,
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::notation_parser::parse_motion;
+    use crate::static_types::Leniency;
+
+    const PUNCH: &[ButtonNotation] = &[ButtonNotation::P];
+
+    fn history(digits: &str) -> Vec<NumericalNotation> {
+        digits
+            .chars()
+            .map(|digit| NumericalNotation::from_digit(digit).unwrap())
+            .collect()
+    }
+
+    // One history entry per frame, as the game samples it
+    fn game_frames(position_history: &[NumericalNotation]) -> Vec<u64> {
+        (0..position_history.len() as u64).collect()
+    }
+
+    fn check(
+        position_history: &[NumericalNotation],
+        move_map: &HashMap<MoveId, MoveDefinition>,
+        buttons: &[ButtonNotation],
+        edge: Edge,
+    ) -> Option<MatchResult> {
+        let history_frames = game_frames(position_history);
+        check_move_sequence(position_history, &history_frames, move_map, buttons, edge)
+    }
+
+    fn diagnose(
+        position_history: &[NumericalNotation],
+        move_map: &HashMap<MoveId, MoveDefinition>,
+        buttons: &[ButtonNotation],
+    ) -> Option<Diagnosis> {
+        diagnose_attempt(
+            position_history,
+            &game_frames(position_history),
+            move_map,
+            buttons,
+        )
+    }
+
+    fn move_map(moves: &[(&str, &str)]) -> HashMap<MoveId, MoveDefinition> {
+        moves
+            .iter()
+            .map(|(name, notation)| (MoveId(name.to_string()), parse_motion(notation).unwrap()))
+            .collect()
+    }
+
+    fn detected(position_history: &[NumericalNotation], moves: &[(&str, &str)]) -> Option<String> {
+        pressing(PUNCH, position_history, moves)
+    }
+
+    fn pressing(
+        buttons: &[ButtonNotation],
+        position_history: &[NumericalNotation],
+        moves: &[(&str, &str)],
+    ) -> Option<String> {
+        check(position_history, &move_map(moves), buttons, Edge::Press)
+            .map(|result| result.move_id.0)
+    }
 
     #[test]
-    fn test_count_sequence() {
+    fn test_match_result_total_frames() {
+        let dp = move_map(&[("DP", "623P")]);
+        let total_frames = |position_history: &[NumericalNotation]| {
+            check(position_history, &dp, PUNCH, Edge::Press)
+                .unwrap()
+                .total_frames
+        };
+
         let position_history = vec![
             NumericalNotation::Six,
             NumericalNotation::Two,
             NumericalNotation::Three,
         ];
-        let move_sequence = vec![
-            NumericalNotation::Six,
-            NumericalNotation::Two,
-            NumericalNotation::Three,
-        ];
-        assert_eq!(count_distance(&position_history, &move_sequence), 3);
+        assert_eq!(total_frames(&position_history), 3);
 
         let position_history = vec![
             NumericalNotation::Six,
@@ -135,12 +665,7 @@ mod tests {
             NumericalNotation::Two,
             NumericalNotation::Three,
         ];
-        let move_sequence = vec![
-            NumericalNotation::Six,
-            NumericalNotation::Two,
-            NumericalNotation::Three,
-        ];
-        assert_eq!(count_distance(&position_history, &move_sequence), 4);
+        assert_eq!(total_frames(&position_history), 4);
 
         let position_history = vec![
             NumericalNotation::Six,
@@ -150,12 +675,7 @@ mod tests {
             NumericalNotation::Two,
             NumericalNotation::Three,
         ];
-        let move_sequence = vec![
-            NumericalNotation::Six,
-            NumericalNotation::Two,
-            NumericalNotation::Three,
-        ];
-        assert_eq!(count_distance(&position_history, &move_sequence), 3);
+        assert_eq!(total_frames(&position_history), 3);
     }
 
     #[test]
@@ -171,7 +691,15 @@ mod tests {
             NumericalNotation::Three,
         ];
 
-        assert!(is_valid_sequence(&position_history, &move_sequence));
+        assert!(
+            find_sequence(
+                &position_history,
+                &game_frames(&position_history),
+                &move_sequence,
+                7
+            )
+            .is_some()
+        );
 
         let position_history = vec![
             NumericalNotation::Six,
@@ -184,7 +712,15 @@ mod tests {
             NumericalNotation::Two,
             NumericalNotation::Three,
         ];
-        assert!(is_valid_sequence(&position_history, &move_sequence));
+        assert!(
+            find_sequence(
+                &position_history,
+                &game_frames(&position_history),
+                &move_sequence,
+                7
+            )
+            .is_some()
+        );
     }
 
     #[test]
@@ -204,6 +740,387 @@ mod tests {
             NumericalNotation::Three,
         ];
 
-        assert!(!is_valid_sequence(&position_history, &move_sequence));
+        assert!(
+            find_sequence(
+                &position_history,
+                &game_frames(&position_history),
+                &move_sequence,
+                7
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_longest_overlapping_motion_wins() {
+        let moves = [("QCB", "214P"), ("HCB", "63214P")];
+        assert_eq!(
+            detected(&history("563214"), &moves),
+            Some("HCB".to_string())
+        );
+        assert_eq!(detected(&history("55214"), &moves), Some("QCB".to_string()));
+
+        let moves = [("QCF", "236P"), ("Super", "236236P")];
+        assert_eq!(
+            detected(&history("5236236"), &moves),
+            Some("Super".to_string())
+        );
+        assert_eq!(detected(&history("55236"), &moves), Some("QCF".to_string()));
+
+        let moves = [("QCF", "236P"), ("HCF", "41236P")];
+        assert_eq!(
+            detected(&history("541236"), &moves),
+            Some("HCF".to_string())
+        );
+    }
+
+    #[test]
+    fn test_explicit_priority_beats_length() {
+        let mut moves = move_map(&[("QCB", "214P"), ("HCB", "63214P")]);
+        moves.get_mut(&MoveId("QCB".to_string())).unwrap().priority = 1;
+
+        let result = check(&history("563214"), &moves, PUNCH, Edge::Press).unwrap();
+        assert_eq!(result.move_id, MoveId("QCB".to_string()));
+    }
+
+    #[test]
+    fn test_priority_resolution_is_deterministic() {
+        // Same length and priority, only the name can break the tie
+        let moves = [("Beta", "236P"), ("Alpha", "236P")];
+        for _ in 0..32 {
+            assert_eq!(
+                detected(&history("5236"), &moves),
+                Some("Alpha".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_moves_need_their_button() {
+        let moves = [("Hadoken", "236P"), ("Tatsu", "214K"), ("Shoryu", "623HP")];
+        let heavy_punch = &[ButtonNotation::HP];
+
+        assert_eq!(
+            pressing(heavy_punch, &history("5236"), &moves),
+            Some("Hadoken".to_string())
+        );
+        assert_eq!(pressing(heavy_punch, &history("5214"), &moves), None);
+        assert_eq!(
+            pressing(&[ButtonNotation::LP], &history("5623"), &moves),
+            None
+        );
+        assert_eq!(
+            pressing(heavy_punch, &history("5623"), &moves),
+            Some("Shoryu".to_string())
+        );
+    }
+
+    #[test]
+    fn test_repeated_direction_needs_separate_presses() {
+        let moves = [("22", "22P")];
+        assert_eq!(detected(&history("5252"), &moves), Some("22".to_string()));
+        assert_eq!(detected(&history("55222"), &moves), None);
+    }
+
+    #[test]
+    fn test_rotation_from_any_start_and_direction() {
+        let moves = [("360", "360P")];
+        // Counter-clockwise from forward, clockwise from back, starting from down
+        assert_eq!(
+            detected(&history("56987412"), &moves),
+            Some("360".to_string())
+        );
+        assert_eq!(
+            detected(&history("54789632"), &moves),
+            Some("360".to_string())
+        );
+        assert_eq!(
+            detected(&history("52369874"), &moves),
+            Some("360".to_string())
+        );
+        // Three quarters, reversing halfway and passing through neutral are not enough
+        assert_eq!(detected(&history("569874"), &moves), None);
+        assert_eq!(detected(&history("5698963"), &moves), None);
+        assert_eq!(detected(&history("69874512"), &moves), None);
+    }
+
+    #[test]
+    fn test_720_takes_priority_over_360() {
+        let moves = [("360", "360P"), ("720", "720P"), ("HCB", "63214P")];
+        assert_eq!(
+            detected(&history("5698741236987412"), &moves),
+            Some("720".to_string())
+        );
+        assert_eq!(
+            detected(&history("563214"), &moves),
+            Some("HCB".to_string())
+        );
+        assert_eq!(
+            detected(&history("56321478"), &moves),
+            Some("360".to_string())
+        );
+    }
+
+    fn charge_history(charge: &str, frames: usize, rest: &str) -> Vec<NumericalNotation> {
+        let mut position_history = history("55");
+        position_history.extend(history(&charge.repeat(frames)));
+        position_history.extend(history(rest));
+        position_history
+    }
+
+    #[test]
+    fn test_charge_needs_enough_frames() {
+        let mut moves = move_map(&[("Sonic Boom", "[4]6P")]);
+        moves
+            .get_mut(&MoveId("Sonic Boom".to_string()))
+            .unwrap()
+            .charge_frames = 40;
+
+        let result = check(&charge_history("4", 40, "6"), &moves, PUNCH, Edge::Press).unwrap();
+        assert_eq!(result.move_id, MoveId("Sonic Boom".to_string()));
+        assert_eq!(result.charge_frames, Some(40));
+        assert_eq!(result.total_frames, 41);
+
+        assert!(check(&charge_history("4", 39, "6"), &moves, PUNCH, Edge::Press).is_none());
+
+        // Charge must be released into the attack within the step gap
+        assert!(
+            check(
+                &charge_history("4", 40, "55556"),
+                &moves,
+                PUNCH,
+                Edge::Press
+            )
+            .is_some()
+        );
+        assert!(
+            check(
+                &charge_history("4", 40, "555555556"),
+                &moves,
+                PUNCH,
+                Edge::Press
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_diagonal_charges_both_directions() {
+        let moves = [("Sonic Boom", "[4]6P"), ("Flash Kick", "[2]8K")];
+        assert_eq!(
+            detected(&charge_history("1", 45, "6"), &moves),
+            Some("Sonic Boom".to_string())
+        );
+        assert_eq!(
+            pressing(&[ButtonNotation::MK], &charge_history("1", 45, "8"), &moves),
+            Some("Flash Kick".to_string())
+        );
+        // Moving between 4 and 1 keeps the back charge going
+        assert_eq!(
+            detected(
+                &charge_history("4", 20, &format!("{}6", "1".repeat(25))),
+                &moves
+            ),
+            Some("Sonic Boom".to_string())
+        );
+        assert_eq!(detected(&charge_history("7", 45, "8"), &moves), None);
+    }
+
+    #[test]
+    fn test_leniency_windows() {
+        let mut moves = move_map(&[("DP", "623P")]);
+        let dp = moves.get_mut(&MoveId("DP".to_string())).unwrap();
+        dp.leniency = Leniency {
+            step_gap: 4,
+            motion_window: 6,
+            button_window: 2,
+        };
+        let dp_detected =
+            |digits: &str| check(&history(digits), &moves, PUNCH, Edge::Press).is_some();
+
+        assert!(dp_detected("5623"));
+        // Button pressed up to two frames after leaving the last direction
+        assert!(dp_detected("562355"));
+        assert!(!dp_detected("5623555"));
+        // Step gap of 4 frames, whole motion within 6 frames
+        assert!(dp_detected("5622223"));
+        assert!(!dp_detected("56222223"));
+        assert!(dp_detected("56222233"));
+        assert!(!dp_detected("562222333"));
+    }
+
+    #[test]
+    fn test_match_result_details() {
+        let moves = move_map(&[("DP", "623P")]);
+        let mut dp = moves[&MoveId("DP".to_string())].clone();
+        dp.leniency.button_window = 3;
+        let moves = HashMap::from([(MoveId("DP".to_string()), dp)]);
+
+        let result = check(&history("5566222355"), &moves, PUNCH, Edge::Press).unwrap();
+        assert_eq!(
+            result.steps,
+            vec![
+                MatchedStep {
+                    direction: NumericalNotation::Six,
+                    history_index: 3,
+                },
+                MatchedStep {
+                    direction: NumericalNotation::Two,
+                    history_index: 6,
+                },
+                MatchedStep {
+                    direction: NumericalNotation::Three,
+                    history_index: 7,
+                },
+            ]
+        );
+        assert_eq!(result.step_gaps, vec![3, 1]);
+        assert_eq!(result.charge_frames, None);
+        assert_eq!(result.button_delay, 2);
+        assert_eq!(result.total_frames, 7);
+
+        let moves = move_map(&[("360", "360P")]);
+        let result = check(&history("56987412"), &moves, PUNCH, Edge::Press).unwrap();
+        let directions: Vec<_> = result.steps.iter().map(|step| step.direction).collect();
+        assert_eq!(directions, history("6842"));
+        assert_eq!(result.step_gaps, vec![2, 2, 2]);
+    }
+
+    #[test]
+    fn test_counts_frames_not_entries() {
+        // 6, 2 and 3 all within frame 1, the button two frames later
+        let position_history = history("56235");
+        let history_frames = [0, 1, 1, 1, 3];
+        let moves = move_map(&[("DP", "623P")]);
+        let result = check_move_sequence(
+            &position_history[..4],
+            &history_frames[..4],
+            &moves,
+            PUNCH,
+            Edge::Press,
+        )
+        .unwrap();
+        assert_eq!(result.step_gaps, vec![0, 0]);
+        assert_eq!(result.button_delay, 0);
+        assert_eq!(result.total_frames, 1);
+
+        let mut dp = moves[&MoveId("DP".to_string())].clone();
+        dp.leniency.step_gap = 1;
+        let moves = HashMap::from([(MoveId("DP".to_string()), dp)]);
+        // Four entries apart but only one frame
+        let position_history = history("56523");
+        let history_frames = [0, 1, 1, 1, 2];
+        assert!(
+            check_move_sequence(
+                &position_history,
+                &history_frames,
+                &moves,
+                PUNCH,
+                Edge::Press
+            )
+            .is_some()
+        );
+    }
+
+    fn diagnosis(position_history: &[NumericalNotation], moves: &[(&str, &str)]) -> Option<String> {
+        diagnose(position_history, &move_map(moves), PUNCH).map(|diagnosis| diagnosis.to_string())
+    }
+
+    #[test]
+    fn test_diagnose_missing_step() {
+        let moves = [("DP", "623P"), ("QCF", "236P"), ("HCB", "63214P")];
+        assert_eq!(
+            diagnosis(&history("5563"), &moves),
+            Some("DP: got 6-3 but missing 2".to_string())
+        );
+        assert_eq!(
+            diagnosis(&history("55214"), &moves),
+            Some("HCB: got 2-1-4 but missing 6-3".to_string())
+        );
+        assert_eq!(diagnosis(&history("5555"), &moves), None);
+    }
+
+    #[test]
+    fn test_diagnose_dp_rolled_into_qcf() {
+        // Going all the way to 6 after the DP motion leaves 3 too early
+        assert_eq!(
+            diagnosis(&history("56236"), &[("DP", "623P")]),
+            Some("DP: button pressed 1f after motion window closed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_diagnose_slow_inputs() {
+        let moves = [("DP", "623P")];
+        assert_eq!(
+            diagnosis(&history("5622222222223"), &moves),
+            Some("DP: too slow between 6 and 2 (10f > 7f)".to_string())
+        );
+        assert_eq!(
+            diagnosis(&history("56232223"), &moves),
+            None,
+            "a completed motion has nothing to diagnose"
+        );
+        assert_eq!(
+            diagnosis(&history("5623555"), &moves),
+            Some("DP: button pressed 3f after motion window closed".to_string())
+        );
+
+        let mut dp = move_map(&moves);
+        dp.get_mut(&MoveId("DP".to_string()))
+            .unwrap()
+            .leniency
+            .motion_window = 8;
+        let result = diagnose(&history("56222222333"), &dp, PUNCH).unwrap();
+        assert_eq!(
+            result.reason,
+            FailureReason::MotionTooSlow {
+                frames: 9,
+                limit: 8
+            }
+        );
+    }
+
+    #[test]
+    fn test_diagnose_short_charge() {
+        assert_eq!(
+            diagnosis(&charge_history("4", 30, "6"), &[("Sonic Boom", "[4]6P")]),
+            Some("Sonic Boom: charge held 30f, needs 45f".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normals_are_not_missed_specials() {
+        let moves = crate::move_loader::default_move_map(None);
+        let normal = |digits: &str| {
+            diagnose(
+                &history(&format!("{}{}", "5".repeat(60), digits)),
+                &moves,
+                PUNCH,
+            )
+        };
+        assert_eq!(normal("5"), None, "5P");
+        assert_eq!(normal("6"), None, "6P");
+        assert_eq!(normal("2"), None, "2P");
+        assert_eq!(normal("3"), None, "3P");
+        // A forward touched long before the button is no charge release
+        assert_eq!(normal(&format!("6{}", "5".repeat(46))), None);
+    }
+
+    #[test]
+    fn test_negative_edge_is_opt_in() {
+        let mut moves = move_map(&[("DP", "623P"), ("QCF", "236P")]);
+        moves
+            .get_mut(&MoveId("QCF".to_string()))
+            .unwrap()
+            .negative_edge = true;
+
+        assert!(check(&history("5623"), &moves, PUNCH, Edge::Release).is_none());
+        let result = check(&history("5236"), &moves, PUNCH, Edge::Release).unwrap();
+        assert_eq!(result.move_id, MoveId("QCF".to_string()));
+        assert_eq!(result.edge, Edge::Release);
+
+        let result = check(&history("5236"), &moves, PUNCH, Edge::Press).unwrap();
+        assert_eq!(result.edge, Edge::Press);
     }
 }
@@ -1,36 +1,49 @@
-use crate::static_types::{ButtonState, ButtonsStates, NumericalNotation, PossibleCoordinates};
-use gilrs::{Button, Event, EventType};
-
-pub fn calculate_position(buttons_state: &ButtonsStates) -> NumericalNotation {
-    let vertical_coordinate: PossibleCoordinates;
-    match (&buttons_state.up, &buttons_state.down) {
-        (ButtonState::Released, ButtonState::Released) => {
-            vertical_coordinate = PossibleCoordinates::Zero
-        }
-        (ButtonState::Pressed, ButtonState::Released) => {
-            vertical_coordinate = PossibleCoordinates::One
-        }
-        (ButtonState::Released, ButtonState::Pressed) => {
-            vertical_coordinate = PossibleCoordinates::MinusOne
-        }
+use crate::static_types::{
+    ATTACK_BUTTONS, ButtonEdge, ButtonState, ButtonsStates, Edge, GateShape, InputButton,
+    InputChange, InputEvent, NumericalNotation, PossibleCoordinates, SocdMode, StickAxis,
+    StickSettings,
+};
+
+fn coordinate(direction: InputButton) -> PossibleCoordinates {
+    match direction {
+        InputButton::Up | InputButton::Right => PossibleCoordinates::One,
+        _ => PossibleCoordinates::MinusOne,
+    }
+}
+
+// Coordinate of an axis whose two directions are both held
+fn resolve_conflict(
+    socd_mode: SocdMode,
+    last_pressed: Option<InputButton>,
+    up_wins: bool,
+) -> PossibleCoordinates {
+    match (socd_mode, last_pressed) {
+        (SocdMode::UpPriority, _) if up_wins => PossibleCoordinates::One,
+        (SocdMode::LastInput, Some(last)) => coordinate(last),
+        (SocdMode::FirstInput, Some(last)) => match coordinate(last) {
+            PossibleCoordinates::One => PossibleCoordinates::MinusOne,
+            _ => PossibleCoordinates::One,
+        },
+        _ => PossibleCoordinates::Zero,
+    }
+}
+
+pub fn calculate_position(buttons_state: &ButtonsStates, socd_mode: SocdMode) -> NumericalNotation {
+    let vertical_coordinate = match (&buttons_state.up, &buttons_state.down) {
+        (ButtonState::Released, ButtonState::Released) => PossibleCoordinates::Zero,
+        (ButtonState::Pressed, ButtonState::Released) => PossibleCoordinates::One,
+        (ButtonState::Released, ButtonState::Pressed) => PossibleCoordinates::MinusOne,
         (ButtonState::Pressed, ButtonState::Pressed) => {
-            vertical_coordinate = PossibleCoordinates::Zero
+            resolve_conflict(socd_mode, buttons_state.last_vertical, true)
         }
     };
 
-    let horizontal_coordinate: PossibleCoordinates;
-    match (&buttons_state.left, &buttons_state.right) {
-        (ButtonState::Released, ButtonState::Released) => {
-            horizontal_coordinate = PossibleCoordinates::Zero
-        }
-        (ButtonState::Pressed, ButtonState::Released) => {
-            horizontal_coordinate = PossibleCoordinates::MinusOne
-        }
-        (ButtonState::Released, ButtonState::Pressed) => {
-            horizontal_coordinate = PossibleCoordinates::One
-        }
+    let horizontal_coordinate = match (&buttons_state.left, &buttons_state.right) {
+        (ButtonState::Released, ButtonState::Released) => PossibleCoordinates::Zero,
+        (ButtonState::Pressed, ButtonState::Released) => PossibleCoordinates::MinusOne,
+        (ButtonState::Released, ButtonState::Pressed) => PossibleCoordinates::One,
         (ButtonState::Pressed, ButtonState::Pressed) => {
-            horizontal_coordinate = PossibleCoordinates::Zero
+            resolve_conflict(socd_mode, buttons_state.last_horizontal, false)
         }
     };
 
@@ -47,38 +60,251 @@ pub fn calculate_position(buttons_state: &ButtonsStates) -> NumericalNotation {
     }
 }
 
-// Event parser to update the current state of the cardinal directions
-pub fn parse_event(event: &Event, current_state: &mut ButtonsStates) {
-    match event.event {
-        EventType::ButtonPressed(button, _) => match button {
-            Button::DPadUp => current_state.up = ButtonState::Pressed,
-            Button::DPadDown => current_state.down = ButtonState::Pressed,
-            Button::DPadLeft => current_state.left = ButtonState::Pressed,
-            Button::DPadRight => current_state.right = ButtonState::Pressed,
-            Button::North => current_state.attack_north = ButtonState::Pressed,
-            Button::South => current_state.attack_south = ButtonState::Pressed,
-            Button::East => current_state.attack_east = ButtonState::Pressed,
-            Button::West => current_state.attack_west = ButtonState::Pressed,
-            _ => {}
-        },
-        EventType::ButtonReleased(button, _) => match button {
-            Button::DPadUp => current_state.up = ButtonState::Released,
-            Button::DPadDown => current_state.down = ButtonState::Released,
-            Button::DPadLeft => current_state.left = ButtonState::Released,
-            Button::DPadRight => current_state.right = ButtonState::Released,
-            Button::North => current_state.attack_north = ButtonState::Released,
-            Button::South => current_state.attack_south = ButtonState::Released,
-            Button::East => current_state.attack_east = ButtonState::Released,
-            Button::West => current_state.attack_west = ButtonState::Released,
-            _ => {}
-        },
-        _ => {}
+// Event parser to update the current state of the cardinal directions and
+// attack buttons, whatever input source the event came from
+pub fn apply_event(event: &InputEvent, current_state: &mut ButtonsStates) {
+    let (button, state) = match event.change {
+        InputChange::Button(button, state) => (button, state),
+        InputChange::Stick(StickAxis::X, value) => {
+            current_state.stick.0 = value;
+            return;
+        }
+        InputChange::Stick(StickAxis::Y, value) => {
+            current_state.stick.1 = value;
+            return;
+        }
+    };
+    *current_state.button_mut(button) = state;
+    if state == ButtonState::Pressed {
+        match button {
+            InputButton::Up | InputButton::Down => current_state.last_vertical = Some(button),
+            InputButton::Left | InputButton::Right => current_state.last_horizontal = Some(button),
+            InputButton::Attack(_) => {}
+        }
+    }
+}
+
+// Distance of the stick from the center, 1.0 being the edge of the gate
+fn gate_distance((x, y): (f32, f32), gate: GateShape) -> f32 {
+    match gate {
+        GateShape::Square => x.abs().max(y.abs()),
+        GateShape::Circle => x.hypot(y),
+        // Corners of the octagon sit on the eight directions, measure against
+        // the closest of its sides
+        GateShape::Octagon => {
+            let side = std::f32::consts::PI / 8.0;
+            (0..8)
+                .map(|side_idx| {
+                    let normal = side * (2 * side_idx + 1) as f32;
+                    x * normal.cos() + y * normal.sin()
+                })
+                .fold(0.0, f32::max)
+                / side.cos()
+        }
+    }
+}
+
+// Quantizes the raw stick position into one of the eight directions
+pub fn stick_direction(stick: (f32, f32), settings: &StickSettings) -> NumericalNotation {
+    if gate_distance(stick, settings.gate) <= settings.deadzone {
+        return NumericalNotation::Five;
+    }
+
+    // Angle from the closest diagonal decides between it and a cardinal
+    let angle = stick.1.atan2(stick.0).to_degrees().rem_euclid(360.0);
+    let diagonal_center = (angle / 90.0).floor() * 90.0 + 45.0;
+    if (angle - diagonal_center).abs() <= settings.diagonal_width / 2.0 {
+        return match diagonal_center as u16 {
+            45 => NumericalNotation::Nine,
+            135 => NumericalNotation::Seven,
+            225 => NumericalNotation::One,
+            _ => NumericalNotation::Three,
+        };
+    }
+    match ((angle + 45.0) / 90.0) as u16 % 4 {
+        0 => NumericalNotation::Six,
+        1 => NumericalNotation::Eight,
+        2 => NumericalNotation::Four,
+        _ => NumericalNotation::Two,
     }
 }
 
 pub fn is_attack_pressed(buttons_state: &ButtonsStates) -> bool {
-    buttons_state.attack_north == ButtonState::Pressed
-        || buttons_state.attack_south == ButtonState::Pressed
-        || buttons_state.attack_east == ButtonState::Pressed
-        || buttons_state.attack_west == ButtonState::Pressed
+    buttons_state.attacks.contains(&ButtonState::Pressed)
+}
+
+// Attack buttons that changed state between two frames, so a held button
+// gives a single press instead of one per frame
+pub fn detect_edges(
+    previous_state: &ButtonsStates,
+    current_state: &ButtonsStates,
+    frame: u64,
+) -> Vec<ButtonEdge> {
+    ATTACK_BUTTONS
+        .iter()
+        .filter_map(|&button| {
+            let edge = match (previous_state.attack(button), current_state.attack(button)) {
+                (ButtonState::Released, ButtonState::Pressed) => Edge::Press,
+                (ButtonState::Pressed, ButtonState::Released) => Edge::Release,
+                _ => return None,
+            };
+            Some(ButtonEdge {
+                button,
+                edge,
+                frame,
+            })
+        })
+        .collect()
+}
+
+// Every attack button transition of the frame, so lossless mode sees a
+// button pressed and released before the frame ended
+pub fn detect_event_edges(
+    previous_state: &ButtonsStates,
+    events: &[InputEvent],
+    frame: u64,
+) -> Vec<ButtonEdge> {
+    let mut buttons_state = previous_state.clone();
+    let mut edges = Vec::new();
+    for event in events {
+        let InputChange::Button(InputButton::Attack(button), state) = event.change else {
+            continue;
+        };
+        let edge = match (buttons_state.attack(button), state) {
+            (ButtonState::Released, ButtonState::Pressed) => Edge::Press,
+            (ButtonState::Pressed, ButtonState::Released) => Edge::Release,
+            _ => continue,
+        };
+        *buttons_state.attack_mut(button) = state;
+        edges.push(ButtonEdge {
+            button,
+            edge,
+            frame,
+        });
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_source::parse_recording;
+    use crate::static_types::AttackButton;
+    use std::time::Duration;
+
+    #[test]
+    fn test_held_button_gives_one_press() {
+        let idle = ButtonsStates::default();
+        let mut holding = ButtonsStates::default();
+        *holding.attack_mut(AttackButton::West) = ButtonState::Pressed;
+
+        let mut edges = detect_edges(&idle, &holding, 10);
+        for frame in 11..40 {
+            edges.extend(detect_edges(&holding, &holding, frame));
+        }
+        edges.extend(detect_edges(&holding, &idle, 40));
+
+        assert_eq!(
+            edges,
+            vec![
+                ButtonEdge {
+                    button: AttackButton::West,
+                    edge: Edge::Press,
+                    frame: 10,
+                },
+                ButtonEdge {
+                    button: AttackButton::West,
+                    edge: Edge::Release,
+                    frame: 40,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_edges_keep_sub_frame_taps() {
+        let idle = ButtonsStates::default();
+        let events = parse_recording("3 west press\n6 west release\n7 west release").unwrap();
+        let edges = detect_event_edges(&idle, &events, 0);
+        let kinds: Vec<_> = edges.iter().map(|button_edge| button_edge.edge).collect();
+        assert_eq!(kinds, vec![Edge::Press, Edge::Release]);
+        assert!(detect_edges(&idle, &idle, 0).is_empty());
+    }
+
+    // Presses `first` then `second` and reads the resulting direction
+    fn socd_position(socd_mode: SocdMode, first: InputButton, second: InputButton) -> u8 {
+        let mut buttons_state = ButtonsStates::default();
+        for button in [first, second] {
+            let event = InputEvent {
+                time: Duration::ZERO,
+                device: 0,
+                change: InputChange::Button(button, ButtonState::Pressed),
+            };
+            apply_event(&event, &mut buttons_state);
+        }
+        calculate_position(&buttons_state, socd_mode) as u8
+    }
+
+    #[test]
+    fn test_socd_modes() {
+        use InputButton::{Down, Left, Right, Up};
+
+        assert_eq!(socd_position(SocdMode::Neutral, Left, Right), 5);
+        assert_eq!(socd_position(SocdMode::Neutral, Up, Down), 5);
+
+        assert_eq!(socd_position(SocdMode::LastInput, Left, Right), 6);
+        assert_eq!(socd_position(SocdMode::LastInput, Right, Left), 4);
+        assert_eq!(socd_position(SocdMode::LastInput, Up, Down), 2);
+
+        assert_eq!(socd_position(SocdMode::FirstInput, Left, Right), 4);
+        assert_eq!(socd_position(SocdMode::FirstInput, Down, Up), 2);
+
+        assert_eq!(socd_position(SocdMode::UpPriority, Down, Up), 8);
+        assert_eq!(socd_position(SocdMode::UpPriority, Up, Down), 8);
+        assert_eq!(socd_position(SocdMode::UpPriority, Left, Right), 5);
+    }
+
+    #[test]
+    fn test_stick_direction() {
+        let settings = StickSettings::default();
+        let direction = |stick| stick_direction(stick, &settings) as u8;
+
+        assert_eq!(direction((0.1, -0.2)), 5);
+        assert_eq!(direction((0.9, 0.1)), 6);
+        assert_eq!(direction((0.0, -1.0)), 2);
+        assert_eq!(direction((-0.7, 0.7)), 7);
+        assert_eq!(direction((0.7, -0.7)), 3);
+        // 20 degrees up from right is outside the 9 sector
+        assert_eq!(direction((0.94, 0.34)), 6);
+
+        let wide_diagonals = StickSettings {
+            diagonal_width: 70.0,
+            ..settings
+        };
+        assert_eq!(stick_direction((0.94, 0.34), &wide_diagonals) as u8, 9);
+    }
+
+    #[test]
+    fn test_deadzone_follows_gate_shape() {
+        let gate = |gate| StickSettings {
+            deadzone: 0.25,
+            diagonal_width: 45.0,
+            gate,
+        };
+
+        // Diagonal at 0.2 on both axes is 0.28 from the center
+        assert_eq!(
+            stick_direction((0.2, 0.2), &gate(GateShape::Circle)) as u8,
+            9
+        );
+        assert_eq!(
+            stick_direction((0.2, 0.2), &gate(GateShape::Square)) as u8,
+            5
+        );
+        // Octagon corners reach as far as the circle
+        assert_eq!(
+            stick_direction((0.2, 0.2), &gate(GateShape::Octagon)) as u8,
+            9
+        );
+    }
 }
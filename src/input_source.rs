@@ -0,0 +1,488 @@
+use crate::static_types::{
+    ButtonState, FRAME_DURATION, InputButton, InputChange, InputEvent, SessionCommand, StickAxis,
+    frame_of,
+};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// How long the input thread waits before polling again. Events keep the
+// time their source gave them, so this only adds latency, not timing error.
+pub const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+// How long after its end a frame is played, so the events of its last
+// moments made it through the input thread
+pub const FRAME_DELAY: Duration = Duration::from_millis(4);
+
+// Anything that can drive the viewer: a controller, the keyboard, a
+// recording or a scripted sequence of presses
+pub trait InputSource {
+    // Events that happened since the previous poll, oldest first
+    fn poll(&mut self) -> Vec<InputEvent>;
+
+    // Session commands given over the span of the last poll, applying before
+    // its events. Only recorded sessions hold any.
+    fn commands(&self) -> Vec<SessionCommand> {
+        Vec::new()
+    }
+
+    // Name the button profile of `device` is saved under
+    fn controller_name(&self, _device: usize) -> Option<String> {
+        None
+    }
+
+    // Whether the source will never send another event
+    fn finished(&self) -> bool {
+        false
+    }
+}
+
+pub enum InputMessage {
+    // A device sent its first event, sent before that event
+    Connected(usize, Option<String>),
+    Event(InputEvent),
+}
+
+// Polls `source` continuously on its own thread so inputs don't wait for the
+// game frame to be picked up. Stops once the source is finished or the
+// receiving end is dropped.
+pub fn spawn_input_thread(
+    mut source: Box<dyn InputSource + Send>,
+    input_tx: Sender<InputMessage>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut connected: Vec<usize> = Vec::new();
+        loop {
+            for event in source.poll() {
+                if !connected.contains(&event.device) {
+                    connected.push(event.device);
+                    let name = source.controller_name(event.device);
+                    if input_tx
+                        .send(InputMessage::Connected(event.device, name))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                if input_tx.send(InputMessage::Event(event)).is_err() {
+                    return;
+                }
+            }
+            if source.finished() {
+                return;
+            }
+            thread::sleep(INPUT_POLL_INTERVAL);
+        }
+    })
+}
+
+// A live source read on the input thread, handed over one frame per poll
+// like a recording: each poll returns the events `frame_of` puts in the next
+// frame, events of later frames wait for their poll. An event that only
+// arrives after its frame was handed over goes to the next one, its time
+// moved to the start of that frame, so a recording of the session plays it
+// on the frame it was applied on.
+pub struct ThreadedSource {
+    input_rx: Receiver<InputMessage>,
+    controller_names: HashMap<usize, Option<String>>,
+    // Read from the thread, not handed over yet
+    pending: VecDeque<InputEvent>,
+    // The frame the next poll hands over
+    frame: u64,
+    disconnected: bool,
+}
+
+impl ThreadedSource {
+    pub fn spawn(source: Box<dyn InputSource + Send>) -> ThreadedSource {
+        let (input_tx, input_rx) = mpsc::channel::<InputMessage>();
+        spawn_input_thread(source, input_tx);
+        ThreadedSource::new(input_rx)
+    }
+
+    // Takes in what an input thread sends on `input_rx`
+    pub fn new(input_rx: Receiver<InputMessage>) -> ThreadedSource {
+        ThreadedSource {
+            input_rx,
+            controller_names: HashMap::new(),
+            pending: VecDeque::new(),
+            frame: 0,
+            disconnected: false,
+        }
+    }
+}
+
+impl InputSource for ThreadedSource {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        loop {
+            match self.input_rx.try_recv() {
+                Ok(InputMessage::Connected(device, name)) => {
+                    self.controller_names.insert(device, name);
+                }
+                Ok(InputMessage::Event(event)) => self.pending.push_back(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        let frame = self.frame;
+        self.frame += 1;
+        let frame_start = FRAME_DURATION * frame as u32;
+        let ready = self
+            .pending
+            .iter()
+            .take_while(|event| frame_of(event.time) <= frame)
+            .count();
+        self.pending
+            .drain(..ready)
+            .map(|event| InputEvent {
+                time: event.time.max(frame_start),
+                ..event
+            })
+            .collect()
+    }
+
+    fn controller_name(&self, device: usize) -> Option<String> {
+        self.controller_names.get(&device).cloned().flatten()
+    }
+
+    // The input thread stopped, its source failed or ran out
+    fn finished(&self) -> bool {
+        self.disconnected && self.pending.is_empty()
+    }
+}
+
+// Plays a recorded session one frame per poll regardless of the wall clock:
+// each poll returns the events timestamped within the next frame and the
+// commands given on it, so a recording goes through exactly the same frames
+// on every run. Clones share the recording.
+#[derive(Clone)]
+pub struct RecordedSource {
+    recording: Rc<Recording>,
+    // First event and command of the next frame
+    next_event: usize,
+    next_command: usize,
+    // Frames played so far, the next frame to play
+    frame: u64,
+    // Commands of the frame the last poll played
+    commands: Vec<SessionCommand>,
+}
+
+impl RecordedSource {
+    pub fn new(recording: Recording) -> RecordedSource {
+        RecordedSource {
+            recording: Rc::new(recording),
+            next_event: 0,
+            next_command: 0,
+            frame: 0,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<RecordedSource, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let recording =
+            parse_session(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(RecordedSource::new(recording))
+    }
+
+    // The frame the next poll plays
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Frames up to and including the one of the last event or command
+    pub fn length(&self) -> u64 {
+        let events_end = self
+            .recording
+            .events
+            .last()
+            .map_or(0, |event| frame_of(event.time) + 1);
+        let commands_end = self
+            .recording
+            .commands
+            .last()
+            .map_or(0, |&(frame, _)| frame + 1);
+        events_end.max(commands_end)
+    }
+
+    // Back to the first frame
+    pub fn rewind(&mut self) {
+        self.next_event = 0;
+        self.next_command = 0;
+        self.frame = 0;
+        self.commands.clear();
+    }
+}
+
+impl InputSource for RecordedSource {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        let frame = self.frame;
+        let events = &self.recording.events[self.next_event..];
+        let events = &events[..events
+            .iter()
+            .take_while(|event| frame_of(event.time) <= frame)
+            .count()];
+        let commands = &self.recording.commands[self.next_command..];
+        let commands = &commands[..commands
+            .iter()
+            .take_while(|&&(command_frame, _)| command_frame <= frame)
+            .count()];
+        self.next_event += events.len();
+        self.next_command += commands.len();
+        self.commands = commands.iter().map(|&(_, command)| command).collect();
+        self.frame += 1;
+        events.to_vec()
+    }
+
+    fn commands(&self) -> Vec<SessionCommand> {
+        self.commands.clone()
+    }
+
+    fn finished(&self) -> bool {
+        self.frame >= self.length()
+    }
+}
+
+// Steps through events one frame per poll regardless of the wall clock, so
+// tests get the same frames on every run
+pub struct ScriptedSource {
+    events: VecDeque<InputEvent>,
+    frame_duration: Duration,
+    polls: u32,
+}
+
+impl ScriptedSource {
+    pub fn new(events: Vec<InputEvent>, frame_duration: Duration) -> ScriptedSource {
+        ScriptedSource {
+            events: events.into(),
+            frame_duration,
+            polls: 0,
+        }
+    }
+}
+
+impl InputSource for ScriptedSource {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        self.polls += 1;
+        let frame_end = self.frame_duration * self.polls;
+        let ready = self
+            .events
+            .iter()
+            .take_while(|event| event.time < frame_end)
+            .count();
+        self.events.drain(..ready).collect()
+    }
+}
+
+// Button and stick events of a recording, and the session commands given
+// on each frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub events: Vec<InputEvent>,
+    pub commands: Vec<(u64, SessionCommand)>,
+}
+
+// Recordings hold one event per line:
+// `<milliseconds> <button> <press|release> [device]` or
+// `<milliseconds> <stick_x|stick_y> <value> [device]`, the device being 0
+// when left out. Blank lines and lines starting with `#` are skipped.
+pub fn parse_recording(contents: &str) -> Result<Vec<InputEvent>, String> {
+    parse_session(contents).map(|recording| recording.events)
+}
+
+// A recording that may also hold the `frame <number> <command>` lines
+// `--record` writes, `<command>` being switch_sides, reset, pause or resume
+pub fn parse_session(contents: &str) -> Result<Recording, String> {
+    let mut events: Vec<InputEvent> = Vec::new();
+    let mut commands: Vec<(u64, SessionCommand)> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fail = |message: String| format!("line {}: {}", index + 1, message);
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if let ["frame", frame, command] = fields[..] {
+            let frame: u64 = frame
+                .parse()
+                .map_err(|_| fail(format!("'{}' is not a frame number", frame)))?;
+            let command = SessionCommand::from_name(command)
+                .ok_or_else(|| fail(format!("unknown command '{}'", command)))?;
+            if commands.last().is_some_and(|&(last, _)| last > frame) {
+                return Err(fail("frames must not go back in time".to_string()));
+            }
+            commands.push((frame, command));
+            continue;
+        }
+        let (millis, button, state, device) = match fields[..] {
+            [millis, button, state] => (millis, button, state, "0"),
+            [millis, button, state, device] => (millis, button, state, device),
+            _ => {
+                return Err(fail(format!(
+                    "expected '<milliseconds> <button> <press|release>', got '{}'",
+                    line
+                )));
+            }
+        };
+        let millis: f64 = millis
+            .parse()
+            .ok()
+            .filter(|millis: &f64| millis.is_finite() && *millis >= 0.0)
+            .ok_or_else(|| fail(format!("'{}' is not a timestamp", millis)))?;
+        let device: usize = device
+            .parse()
+            .map_err(|_| fail(format!("'{}' is not a device number", device)))?;
+        let stick_axis = [StickAxis::X, StickAxis::Y]
+            .into_iter()
+            .find(|axis| axis.name() == button);
+        let change = match stick_axis {
+            Some(axis) => {
+                let value: f32 = state
+                    .parse()
+                    .map_err(|_| fail(format!("'{}' is not a stick position", state)))?;
+                InputChange::Stick(axis, value.clamp(-1.0, 1.0))
+            }
+            None => {
+                let button = InputButton::from_name(button)
+                    .ok_or_else(|| fail(format!("unknown button '{}'", button)))?;
+                let state = match state {
+                    "press" => ButtonState::Pressed,
+                    "release" => ButtonState::Released,
+                    _ => return Err(fail(format!("expected press or release, got '{}'", state))),
+                };
+                InputChange::Button(button, state)
+            }
+        };
+
+        let time = Duration::from_nanos((millis * 1_000_000.0).round() as u64);
+        if events.last().is_some_and(|last| last.time > time) {
+            return Err(fail("timestamps must not go back in time".to_string()));
+        }
+        events.push(InputEvent {
+            time,
+            device,
+            change,
+        });
+    }
+    Ok(Recording { events, commands })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::static_types::AttackButton;
+
+    #[test]
+    fn test_parse_recording() {
+        let events = parse_recording(
+            "# 236P\n0 down press\n20 right press\n\n40 west press\n50 stick_y -0.5\n",
+        )
+        .unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[2],
+            InputEvent {
+                time: Duration::from_millis(40),
+                device: 0,
+                change: InputChange::Button(
+                    InputButton::Attack(AttackButton::West),
+                    ButtonState::Pressed
+                ),
+            }
+        );
+        assert_eq!(events[3].change, InputChange::Stick(StickAxis::Y, -0.5));
+
+        let events = parse_recording("16.667 down press 1").unwrap();
+        assert_eq!(events[0].time, Duration::from_micros(16_667));
+        assert_eq!(events[0].device, 1);
+
+        assert_eq!(
+            parse_recording("0 down press\n10 jump press").unwrap_err(),
+            "line 2: unknown button 'jump'"
+        );
+        assert_eq!(
+            parse_recording("20 down press\n10 down release").unwrap_err(),
+            "line 2: timestamps must not go back in time"
+        );
+
+        let recording = parse_session("0 down press\nframe 3 switch_sides\nframe 3 pause").unwrap();
+        assert_eq!(recording.events.len(), 1);
+        assert_eq!(
+            recording.commands,
+            vec![(3, SessionCommand::SwitchSides), (3, SessionCommand::Pause)]
+        );
+        assert_eq!(
+            parse_session("frame 3 jump").unwrap_err(),
+            "line 1: unknown command 'jump'"
+        );
+    }
+
+    #[test]
+    fn test_scripted_source_steps_by_frame() {
+        let events = parse_recording("0 down press\n10 right press\n40 down release").unwrap();
+        let mut source = ScriptedSource::new(events, Duration::from_millis(16));
+
+        assert_eq!(source.poll().len(), 2);
+        assert!(source.poll().is_empty());
+        assert_eq!(
+            source.poll()[0].change,
+            InputChange::Button(InputButton::Down, ButtonState::Released)
+        );
+        assert!(source.events.is_empty());
+    }
+
+    #[test]
+    fn test_threaded_source_hands_over_frames() {
+        let (input_tx, input_rx) = mpsc::channel::<InputMessage>();
+        let mut source = ThreadedSource::new(input_rx);
+        let events = parse_recording("10 down press\n20 right press\n30 down release").unwrap();
+        input_tx
+            .send(InputMessage::Connected(0, Some("Pad".to_string())))
+            .unwrap();
+        input_tx.send(InputMessage::Event(events[0])).unwrap();
+        input_tx.send(InputMessage::Event(events[1])).unwrap();
+
+        assert_eq!(source.poll(), [events[0]]);
+        assert_eq!(source.controller_name(0).as_deref(), Some("Pad"));
+        // Frame 1 was handed over before the event of its last moment came
+        assert_eq!(source.poll(), [events[1]]);
+        input_tx.send(InputMessage::Event(events[2])).unwrap();
+        drop(input_tx);
+        let late = source.poll();
+        assert_eq!(late[0].time, FRAME_DURATION * 2);
+        assert_eq!(late[0].change, events[2].change);
+        assert!(source.finished());
+    }
+
+    #[test]
+    fn test_recorded_source_plays_frame_by_frame() {
+        let recording = parse_session(
+            "0 down press\n10 right press\nframe 1 switch_sides\n40 down release\nframe 3 reset",
+        )
+        .unwrap();
+        let mut source = RecordedSource::new(recording);
+        assert_eq!(source.length(), 4);
+
+        assert_eq!(source.poll().len(), 2);
+        assert!(source.commands().is_empty());
+        assert!(source.poll().is_empty());
+        assert_eq!(source.commands(), [SessionCommand::SwitchSides]);
+        assert_eq!(source.poll().len(), 1);
+        assert!(source.commands().is_empty());
+        assert!(source.poll().is_empty());
+        assert_eq!(source.commands(), [SessionCommand::Reset]);
+        assert!(source.finished());
+
+        let mut rewound = source.clone();
+        rewound.rewind();
+        assert_eq!((rewound.frame(), source.frame()), (0, 4));
+        assert_eq!(rewound.poll().len(), 2);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::input_source::InputSource;
+use crate::static_types::{
+    AttackButton, ButtonState, FRAME_DURATION, InputButton, InputChange, InputEvent,
+};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_ATTACK_KEYS: [char; 6] = ['u', 'i', 'o', 'j', 'k', 'l'];
+// Up, down, left and right
+pub const DIRECTION_KEYS: [char; 4] = ['w', 's', 'a', 'd'];
+
+// Keys of a hitbox style layout: WASD for directions, one key per attack
+// button, the top row of the face being West, North and the right shoulder,
+// the bottom row South, East and the right trigger, as on a pad
+pub const KEYBOARD_ATTACKS: [AttackButton; 6] = [
+    AttackButton::West,
+    AttackButton::North,
+    AttackButton::RightShoulder,
+    AttackButton::South,
+    AttackButton::East,
+    AttackButton::RightTrigger,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardLayout {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub attacks: [(KeyCode, AttackButton); 6],
+}
+
+impl KeyboardLayout {
+    pub fn new(attack_keys: [char; 6]) -> KeyboardLayout {
+        KeyboardLayout {
+            up: KeyCode::Char(DIRECTION_KEYS[0]),
+            down: KeyCode::Char(DIRECTION_KEYS[1]),
+            left: KeyCode::Char(DIRECTION_KEYS[2]),
+            right: KeyCode::Char(DIRECTION_KEYS[3]),
+            attacks: [0, 1, 2, 3, 4, 5]
+                .map(|idx| (KeyCode::Char(attack_keys[idx]), KEYBOARD_ATTACKS[idx])),
+        }
+    }
+
+    fn button(&self, code: KeyCode) -> Option<InputButton> {
+        // Shift changes the reported character, match keys case insensitively
+        let code = match code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+
+        if code == self.up {
+            return Some(InputButton::Up);
+        }
+        if code == self.down {
+            return Some(InputButton::Down);
+        }
+        if code == self.left {
+            return Some(InputButton::Left);
+        }
+        if code == self.right {
+            return Some(InputButton::Right);
+        }
+        let (_, button) = self.attacks.iter().find(|(key, _)| *key == code)?;
+        Some(InputButton::Attack(*button))
+    }
+}
+
+// Keyboard counterpart of the gamepad button mapping, key repeats are not
+// button changes
+pub fn key_to_button(
+    event: &KeyEvent,
+    layout: &KeyboardLayout,
+) -> Option<(InputButton, ButtonState)> {
+    let button = layout.button(event.code)?;
+    match event.kind {
+        KeyEventKind::Press => Some((button, ButtonState::Pressed)),
+        KeyEventKind::Release => Some((button, ButtonState::Released)),
+        KeyEventKind::Repeat => None,
+    }
+}
+
+// Keys forwarded by the render thread, which owns the terminal, with the
+// instant they were read. Terminals without the keyboard enhancement
+// protocol never report releases, in that case every key press is a tap
+// released one frame later.
+pub struct KeyboardSource {
+    key_rx: Receiver<(Instant, KeyEvent)>,
+    layout: KeyboardLayout,
+    taps: bool,
+    // Tap releases and when they are due
+    pending_releases: Vec<(InputButton, Duration)>,
+    started: Instant,
+}
+
+impl KeyboardSource {
+    // Event times count from `started`, the instant frame 0 starts at
+    pub fn new(
+        key_rx: Receiver<(Instant, KeyEvent)>,
+        layout: KeyboardLayout,
+        taps: bool,
+        started: Instant,
+    ) -> KeyboardSource {
+        KeyboardSource {
+            key_rx,
+            layout,
+            taps,
+            pending_releases: Vec::new(),
+            started,
+        }
+    }
+}
+
+impl InputSource for KeyboardSource {
+    fn controller_name(&self, _device: usize) -> Option<String> {
+        Some("keyboard".to_string())
+    }
+
+    fn poll(&mut self) -> Vec<InputEvent> {
+        let now = self.started.elapsed();
+        let mut events = Vec::new();
+        self.pending_releases.retain(|&(button, release_time)| {
+            if release_time > now {
+                return true;
+            }
+            events.push(InputEvent {
+                time: release_time,
+                device: 0,
+                change: InputChange::Button(button, ButtonState::Released),
+            });
+            false
+        });
+
+        while let Ok((read_at, key_event)) = self.key_rx.try_recv() {
+            let Some((button, state)) = key_to_button(&key_event, &self.layout) else {
+                continue;
+            };
+            let time = read_at.saturating_duration_since(self.started);
+            events.push(InputEvent {
+                time,
+                device: 0,
+                change: InputChange::Button(button, state),
+            });
+            if self.taps && state == ButtonState::Pressed {
+                self.pending_releases.push((button, time + FRAME_DURATION));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::crossterm::event::KeyModifiers;
+    use std::sync::mpsc;
+
+    fn key(c: char, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent::new_with_kind(KeyCode::Char(c), KeyModifiers::NONE, kind)
+    }
+
+    #[test]
+    fn test_keys_map_to_buttons() {
+        let layout = KeyboardLayout::new(DEFAULT_ATTACK_KEYS);
+
+        assert_eq!(
+            key_to_button(&key('S', KeyEventKind::Press), &layout),
+            Some((InputButton::Down, ButtonState::Pressed))
+        );
+        assert_eq!(
+            key_to_button(&key('u', KeyEventKind::Release), &layout),
+            Some((
+                InputButton::Attack(AttackButton::West),
+                ButtonState::Released
+            ))
+        );
+        assert_eq!(
+            key_to_button(&key('s', KeyEventKind::Repeat), &layout),
+            None
+        );
+        assert_eq!(key_to_button(&key('x', KeyEventKind::Press), &layout), None);
+    }
+
+    #[test]
+    fn test_taps_release_a_frame_later() {
+        let (key_tx, key_rx) = mpsc::channel();
+        let started = Instant::now();
+        let mut source = KeyboardSource::new(
+            key_rx,
+            KeyboardLayout::new(DEFAULT_ATTACK_KEYS),
+            true,
+            started,
+        );
+
+        // Stamped with when the key was read, not when it is polled
+        let read_at = started + Duration::from_millis(5);
+        key_tx
+            .send((read_at, key('s', KeyEventKind::Press)))
+            .unwrap();
+        let pressed = source.poll();
+        assert!(source.poll().is_empty());
+        std::thread::sleep((read_at + FRAME_DURATION).saturating_duration_since(Instant::now()));
+        let released = source.poll();
+
+        assert_eq!(pressed.len(), 1);
+        assert_eq!(pressed[0].time, Duration::from_millis(5));
+        assert_eq!(
+            pressed[0].change,
+            InputChange::Button(InputButton::Down, ButtonState::Pressed)
+        );
+        assert_eq!(released.len(), 1);
+        assert_eq!(
+            released[0].change,
+            InputChange::Button(InputButton::Down, ButtonState::Released)
+        );
+        assert_eq!(released[0].time, pressed[0].time + FRAME_DURATION);
+        assert!(source.poll().is_empty());
+    }
+}
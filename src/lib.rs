@@ -0,0 +1,30 @@
+//! Input normalization and motion recognition for fighting game inputs.
+//!
+//! Controllers, the keyboard and recordings produce `InputEvent`s through an
+//! `InputSource`; `replay::Replay` adds the playback controls on top of a
+//! `RecordedSource`.
+//! A `Player` turns them into the per frame direction history and detects the
+//! moves of a move list in it, reading directions as its `PlayerSettings` say.
+//! The lower level steps are public on their own:
+//! `input_reader::calculate_position` cleans up the held directions,
+//! `input_explainer::check_move_sequence` matches a history.
+//!
+//! The terminal frontend and the keyboard source need the `tui` feature, the
+//! gamepad source the `gamepad` feature; both are on by default.
+
+pub mod button_profile;
+#[cfg(feature = "gamepad")]
+pub mod gamepad_reader;
+pub mod headless;
+pub mod input_explainer;
+pub mod input_reader;
+pub mod input_source;
+#[cfg(feature = "tui")]
+pub mod keyboard_reader;
+pub mod move_loader;
+pub mod notation_parser;
+pub mod player;
+#[cfg(feature = "tui")]
+pub mod rendering;
+pub mod replay;
+pub mod static_types;
@@ -1,82 +1,281 @@
-use gilrs::{Button, Gilrs};
+use std::io::{self, Write};
 use std::thread;
 use std::time::Instant;
 
-mod input_explainer;
-mod input_reader;
-mod rendering;
-mod static_types;
-use input_explainer::check_move_sequence;
-use input_reader::{calculate_position, is_attack_pressed, parse_event};
-use rendering::render_grid;
-use static_types::{ButtonState, ButtonsStates, GlobalState, NumericalNotation, create_move_map};
+mod config;
+mod session;
+mod session_recorder;
+mod ticker;
+
+use config::{AppConfig, InputBackend};
+use ratatui::crossterm::{event::KeyEvent, terminal::supports_keyboard_enhancement};
+#[cfg(feature = "gamepad")]
+use rust_input_speed::gamepad_reader::GamepadSource;
+use rust_input_speed::{
+    button_profile::load_profile,
+    headless::header_line,
+    input_explainer::required_history_length,
+    input_source::{FRAME_DELAY, InputSource, ThreadedSource},
+    keyboard_reader::{KeyboardLayout, KeyboardSource},
+    move_loader::{default_move_map, load_move_file},
+    rendering::render_grid,
+    replay::Replay,
+    static_types::{FRAME_DURATION, GlobalState, SessionCommand, UiCommand},
+};
+use session::{Session, control_replay, play_replay_frame};
+use session_recorder::SessionRecorder;
 use std::sync::mpsc;
-use std::time::Duration;
+use ticker::Ticker;
 
 fn main() {
-    let (render_tx, render_rx) = mpsc::channel::<GlobalState>();
+    let config = match AppConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
-    let mut gilrs = Gilrs::new().unwrap();
+    let mut move_map = match &config.moves_path {
+        Some(path) => match load_move_file(path, config.preset) {
+            Ok(move_map) => move_map,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => default_move_map(config.preset),
+    };
 
-    let move_map = create_move_map();
+    if config.negative_edge {
+        for move_definition in move_map.values_mut() {
+            move_definition.negative_edge = true;
+        }
+    }
+
+    let history_length = config
+        .history_length
+        .max(required_history_length(&move_map));
 
-    let mut current_state = ButtonsStates {
-        up: ButtonState::Released,
-        down: ButtonState::Released,
-        left: ButtonState::Released,
-        right: ButtonState::Released,
-        attack_north: ButtonState::Released,
-        attack_south: ButtonState::Released,
-        attack_east: ButtonState::Released,
-        attack_west: ButtonState::Released,
+    let (render_tx, render_rx) = mpsc::channel::<Vec<GlobalState>>();
+
+    // Input times count from the start of frame 0. Each frame is played
+    // `FRAME_DELAY` after it ends, with the events `frame_of` puts in it that
+    // came in by then; later ones go to the next frame.
+    let started = Instant::now();
+    let (key_tx, key_rx) = mpsc::channel::<(Instant, KeyEvent)>();
+    let mut keyboard_enhancement = false;
+    let (mut inputs, key_tx) = match &config.input {
+        #[cfg(feature = "gamepad")]
+        InputBackend::Gamepad => match GamepadSource::new(started) {
+            Ok(source) => (Inputs::Live(ThreadedSource::spawn(Box::new(source))), None),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        #[cfg(not(feature = "gamepad"))]
+        InputBackend::Gamepad => {
+            eprintln!("Built without gamepad support, use --input keyboard or --replay");
+            std::process::exit(1);
+        }
+        InputBackend::Keyboard => {
+            keyboard_enhancement = supports_keyboard_enhancement().unwrap_or(false);
+            let layout = KeyboardLayout::new(config.attack_keys);
+            let source = KeyboardSource::new(key_rx, layout, !keyboard_enhancement, started);
+            (
+                Inputs::Live(ThreadedSource::spawn(Box::new(source))),
+                Some(key_tx),
+            )
+        }
+        InputBackend::Replay(path) => match Replay::from_file(path) {
+            Ok(replay) => (Inputs::Replay(replay), None),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
     };
 
-    let render_handle = thread::spawn(move || render_grid(render_rx));
-    let mut current_position = NumericalNotation::Five;
+    // A --profile file applies to every controller, otherwise each one uses
+    // the profile saved for it
+    let explicit_profile = match &config.profile_path {
+        Some(path) => match load_profile(path) {
+            Ok(profile) => Some(profile),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
-    let mut data_state = GlobalState {
-        current_position: current_position,
-        attack_pressed: false,
-        position_history: Vec::new(),
-        close_requested: false,
-        last_successful_move: vec![],
+    let recorder = match &config.record_path {
+        Some(path) => match SessionRecorder::create(path, &config, &move_map) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
     };
+
+    // Headless runs print JSON lines instead of drawing, and have no commands
+    let (command_tx, command_rx) = mpsc::channel::<UiCommand>();
+    let render = if config.headless {
+        let _ = writeln!(io::stdout(), "{}", header_line());
+        None
+    } else {
+        let render_handle =
+            thread::spawn(move || render_grid(render_rx, command_tx, key_tx, keyboard_enhancement));
+        Some((render_tx, render_handle))
+    };
+
+    let mut session = Session::new(config, move_map, history_length, explicit_profile, recorder);
+    let mut ticker = Ticker::new(FRAME_DURATION, started + FRAME_DELAY);
+    // Nobody watches a headless replay, it plays its frames back to back
+    // instead of in real time
+    let paced = render.is_some() || matches!(inputs, Inputs::Live(_));
+    let mut input_stopped = false;
     loop {
-        let frame_start = Instant::now();
+        let tick = if paced { ticker.wait() } else { 0 };
 
-        while let Some(event) = gilrs.next_event() {
-            parse_event(&event, &mut current_state);
+        // A replay already holds the switches of the drill it recorded
+        let mut switch_sides = matches!(inputs, Inputs::Live(_))
+            && session
+                .config
+                .side_switch
+                .is_some_and(|seconds| tick > 0 && tick.is_multiple_of(seconds * 60));
+        let mut quit = false;
+        let mut session_commands = Vec::new();
+        let mut replay_commands = Vec::new();
+        while let Ok(command) = command_rx.try_recv() {
+            match command {
+                UiCommand::SwitchSides => {
+                    switch_sides = !switch_sides;
+                    if matches!(inputs, Inputs::Replay(_)) {
+                        session.viewer_switched = !session.viewer_switched;
+                    }
+                }
+                UiCommand::TogglePause if matches!(inputs, Inputs::Live(_)) => {
+                    session_commands.push(if session.paused {
+                        SessionCommand::Resume
+                    } else {
+                        SessionCommand::Pause
+                    });
+                }
+                UiCommand::Reset if matches!(inputs, Inputs::Live(_)) => {
+                    session_commands.push(SessionCommand::Reset);
+                }
+                UiCommand::SwitchHistoryMode => {
+                    session.config.history_mode = session.config.history_mode.switched();
+                }
+                UiCommand::Quit => quit = true,
+                command => replay_commands.push(command),
+            }
         }
-
-        data_state.current_position = calculate_position(&current_state);
-        data_state
-            .position_history
-            .push(data_state.current_position.clone());
-        if data_state.position_history.len() > 30 {
-            data_state.position_history.remove(0);
+        if quit {
+            break;
+        }
+        if switch_sides {
+            session_commands.push(SessionCommand::SwitchSides);
+        }
+        for command in session_commands {
+            session.apply_command(tick, command);
         }
 
-        data_state.attack_pressed = is_attack_pressed(&current_state);
-        if data_state.attack_pressed {
-            if let (Some(last_successful_move), distance) =
-                check_move_sequence(&data_state.position_history, &move_map)
-            {
-                data_state
-                    .last_successful_move
-                    .push((last_successful_move, distance));
+        // Live input plays the frame that just ended, a replay as many frames
+        // as its speed asks for. Headless runs print what changed in each.
+        let mut reports = Vec::new();
+        let finished = match &mut inputs {
+            Inputs::Live(source) => {
+                session.play_frame(source, tick);
+                reports.extend(session.reports(tick));
+                source.finished()
+            }
+            Inputs::Replay(replay) => {
+                for command in replay_commands {
+                    control_replay(&mut session, replay, command);
+                }
+                for _ in 0..replay.frames_due() {
+                    let frame = play_replay_frame(&mut session, replay);
+                    reports.extend(session.reports(frame));
+                }
+                for player in &mut session.players {
+                    player.view.replay = Some(replay.status());
+                }
+                replay.finished()
             }
         };
-        match render_tx.send(data_state.clone()) {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!("Failed to send current position: {}", e);
+
+        let tick_jitter = ticker.jitter();
+        for player in &mut session.players {
+            if let Some(e) = &session.recording_error {
+                player.view.notice = Some(e.clone());
             }
+            player.view.history_mode = session.config.history_mode;
+            player.view.paused = session.paused;
+            player.view.tick_jitter = tick_jitter;
         }
+        session.record(SessionRecorder::flush);
+        // Live input never runs out, its thread ending means it failed
+        if finished && matches!(inputs, Inputs::Live(_)) {
+            input_stopped = true;
+            break;
+        }
+        let Some((render_tx, _)) = &render else {
+            let mut stdout = io::stdout().lock();
+            let written = reports
+                .iter()
+                .try_for_each(|line| writeln!(stdout, "{}", line))
+                .and_then(|()| stdout.flush());
+            // Nobody reads the lines any more, e.g. `| head` got enough, or a
+            // recording played to the end has nothing more to report
+            if finished || written.is_err_and(|e| e.kind() == io::ErrorKind::BrokenPipe) {
+                break;
+            }
+            continue;
+        };
+        let views = session
+            .players
+            .iter()
+            .map(|player| player.view.clone())
+            .collect();
+        // The render thread only hangs up when it stopped, its result says why
+        if render_tx.send(views).is_err() {
+            break;
+        }
+    }
+
+    session.record(SessionRecorder::flush);
+    if let Some(e) = &session.recording_error {
+        eprintln!("{}", e);
+    }
 
-        let frame_time = frame_start.elapsed();
-        let target_frame_time = Duration::from_nanos(16_666_667);
-        if frame_time < target_frame_time {
-            thread::sleep(target_frame_time - frame_time);
+    if let Some((render_tx, render_handle)) = render {
+        drop(render_tx);
+        match render_handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            Err(_) => {
+                eprintln!("The render thread panicked");
+                std::process::exit(1);
+            }
         }
     }
+    if input_stopped {
+        eprintln!("The input thread stopped");
+        std::process::exit(1);
+    }
+}
+
+// Controllers and the keyboard are read live on their own thread, recordings
+// are played with the replay controls
+enum Inputs {
+    Live(ThreadedSource),
+    Replay(Replay),
 }
@@ -0,0 +1,487 @@
+use crate::notation_parser::parse_motion;
+use crate::static_types::{LENIENCY_PRESETS, Leniency, MoveDefinition, MoveId};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_MOVES: &str = include_str!("../moves/default.toml");
+
+const FILE_KEYS: [&str; 1] = ["preset"];
+const MOVE_KEYS: [&str; 10] = [
+    "name",
+    "motion",
+    "button",
+    "preset",
+    "step_gap",
+    "motion_window",
+    "button_window",
+    "charge_frames",
+    "priority",
+    "negative_edge",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveFileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MoveFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for MoveFileError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Text(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+// One [[move]] table with the line it starts on, for error reporting
+pub struct MoveTable {
+    pub line: usize,
+    fields: HashMap<String, (usize, Value)>,
+}
+
+impl MoveTable {
+    pub fn check_keys(&self, allowed: &[&str]) -> Result<(), MoveFileError> {
+        for (key, (line, _)) in &self.fields {
+            if !allowed.contains(&key.as_str()) {
+                return Err(MoveFileError {
+                    line: *line,
+                    message: format!(
+                        "unknown key '{}', expected one of {}",
+                        key,
+                        allowed.join(", ")
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Preset named in this table, or `base`, with individual values overridden
+    fn leniency(&self, base: Leniency) -> Result<Leniency, MoveFileError> {
+        let mut leniency = match self.text("preset")? {
+            Some(name) => Leniency::preset(&name).ok_or_else(|| MoveFileError {
+                line: self.fields["preset"].0,
+                message: format!(
+                    "unknown preset '{}', expected one of {}",
+                    name,
+                    LENIENCY_PRESETS.map(|(name, _)| name).join(", ")
+                ),
+            })?,
+            None => base,
+        };
+
+        if let Some(step_gap) = self.integer("step_gap")? {
+            leniency.step_gap = step_gap;
+        }
+        if let Some(motion_window) = self.integer("motion_window")? {
+            leniency.motion_window = motion_window;
+        }
+        if let Some(button_window) = self.integer("button_window")? {
+            leniency.button_window = button_window;
+        }
+        Ok(leniency)
+    }
+
+    // Line a key was set on, or the start of the table
+    pub fn line_of(&self, key: &str) -> usize {
+        self.fields.get(key).map_or(self.line, |(line, _)| *line)
+    }
+
+    pub fn text(&self, key: &str) -> Result<Option<String>, MoveFileError> {
+        match self.fields.get(key) {
+            Some((_, Value::Text(text))) => Ok(Some(text.clone())),
+            Some((line, _)) => Err(MoveFileError {
+                line: *line,
+                message: format!("'{}' must be a string", key),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn required_text(&self, key: &str) -> Result<String, MoveFileError> {
+        self.text(key)?.ok_or_else(|| MoveFileError {
+            line: self.line,
+            message: format!("move is missing '{}'", key),
+        })
+    }
+
+    fn integer(&self, key: &str) -> Result<Option<usize>, MoveFileError> {
+        match self.fields.get(key) {
+            Some((line, Value::Integer(value))) => {
+                usize::try_from(*value)
+                    .map(Some)
+                    .map_err(|_| MoveFileError {
+                        line: *line,
+                        message: format!("'{}' must not be negative", key),
+                    })
+            }
+            Some((line, _)) => Err(MoveFileError {
+                line: *line,
+                message: format!("'{}' must be an integer", key),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, MoveFileError> {
+        match self.fields.get(key) {
+            Some((_, Value::Boolean(value))) => Ok(Some(*value)),
+            Some((line, _)) => Err(MoveFileError {
+                line: *line,
+                message: format!("'{}' must be true or false", key),
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_value(raw: &str, line: usize) -> Result<Value, MoveFileError> {
+    if let Some(quoted) = raw.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    let rest: String = chars.collect();
+                    let rest = rest.trim();
+                    if !rest.is_empty() && !rest.starts_with('#') {
+                        return Err(MoveFileError {
+                            line,
+                            message: format!("unexpected '{}' after string", rest),
+                        });
+                    }
+                    return Ok(Value::Text(text));
+                }
+                '\\' => match chars.next() {
+                    Some('"') => text.push('"'),
+                    Some('\\') => text.push('\\'),
+                    _ => {
+                        return Err(MoveFileError {
+                            line,
+                            message: "unsupported escape sequence, only \\\" and \\\\ work"
+                                .to_string(),
+                        });
+                    }
+                },
+                _ => text.push(c),
+            }
+        }
+        return Err(MoveFileError {
+            line,
+            message: "unterminated string".to_string(),
+        });
+    }
+
+    // Other TOML values would otherwise fail below as a malformed integer
+    let unsupported = match raw.chars().next() {
+        Some('\'') => Some("single-quoted strings"),
+        Some('[') => Some("arrays"),
+        Some('{') => Some("inline tables"),
+        _ => None,
+    };
+    if let Some(unsupported) = unsupported {
+        return Err(MoveFileError {
+            line,
+            message: format!(
+                "{} are not supported, values are double-quoted strings, integers or booleans",
+                unsupported
+            ),
+        });
+    }
+
+    let raw = raw.split('#').next().unwrap_or_default().trim();
+    match raw {
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        _ => raw
+            .parse::<i64>()
+            .map(Value::Integer)
+            .map_err(|_| MoveFileError {
+                line,
+                message: format!(
+                    "expected a string, an integer or a boolean, found '{}'",
+                    raw
+                ),
+            }),
+    }
+}
+
+// Reads the subset of TOML used by move and button profile files: top level
+// keys followed by [[move]] tables, holding `key = "string"`, `key = integer` and
+// `key = true/false` pairs, plus comments. The first table returned holds
+// the top level keys.
+pub fn parse_tables(contents: &str) -> Result<Vec<MoveTable>, MoveFileError> {
+    let mut tables = vec![MoveTable {
+        line: 1,
+        fields: HashMap::new(),
+    }];
+
+    for (index, raw_line) in contents.lines().enumerate() {
+        let line = index + 1;
+        let trimmed = raw_line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        // A header may be followed by a comment, e.g. `[[move]] # DP`
+        let header = trimmed
+            .split_once('#')
+            .map_or(trimmed, |(header, _)| header);
+        if header.trim_end() == "[[move]]" {
+            tables.push(MoveTable {
+                line,
+                fields: HashMap::new(),
+            });
+            continue;
+        }
+
+        let Some((key, raw_value)) = trimmed.split_once('=') else {
+            return Err(MoveFileError {
+                line,
+                message: format!("expected '[[move]]' or 'key = value', found '{}'", trimmed),
+            });
+        };
+        let key = key.trim();
+        let value = parse_value(raw_value.trim(), line)?;
+
+        let Some(table) = tables.last_mut() else {
+            unreachable!("the top level table always exists");
+        };
+        if table
+            .fields
+            .insert(key.to_string(), (line, value))
+            .is_some()
+        {
+            return Err(MoveFileError {
+                line,
+                message: format!("duplicate key '{}'", key),
+            });
+        }
+    }
+
+    Ok(tables)
+}
+
+fn build_move(
+    table: &MoveTable,
+    base_leniency: Leniency,
+) -> Result<(MoveId, MoveDefinition), MoveFileError> {
+    table.check_keys(&MOVE_KEYS)?;
+    let name = table.required_text("name")?;
+    let motion = table.required_text("motion")?;
+    let button = table.required_text("button")?;
+
+    let notation = format!("{}{}", motion, button);
+    let mut definition = parse_motion(&notation).map_err(|e| MoveFileError {
+        line: table.line,
+        message: format!(
+            "move '{}' has invalid notation \"{}\": {}",
+            name, notation, e
+        ),
+    })?;
+
+    definition.leniency = table.leniency(base_leniency)?;
+    if let Some(charge_frames) = table.integer("charge_frames")? {
+        if definition.charge.is_none() {
+            return Err(MoveFileError {
+                line: table.line,
+                message: format!("move '{}' sets charge_frames without a charge", name),
+            });
+        }
+        definition.charge_frames = charge_frames;
+    }
+    if let Some(priority) = table.integer("priority")? {
+        definition.priority = priority;
+    }
+    if let Some(negative_edge) = table.boolean("negative_edge")? {
+        definition.negative_edge = negative_edge;
+    }
+
+    Ok((MoveId(name), definition))
+}
+
+// `preset` comes from the command line and takes precedence over the preset
+// set at the top of the file, while moves can still override either
+pub fn parse_move_list(
+    contents: &str,
+    preset: Option<Leniency>,
+) -> Result<HashMap<MoveId, MoveDefinition>, MoveFileError> {
+    let mut tables = parse_tables(contents)?.into_iter();
+    let file_table = tables.next().expect("the top level table always exists");
+    file_table.check_keys(&FILE_KEYS)?;
+    let base_leniency = match preset {
+        Some(preset) => preset,
+        None => file_table.leniency(Leniency::default())?,
+    };
+
+    let mut move_map = HashMap::new();
+    for table in tables {
+        let (move_id, definition) = build_move(&table, base_leniency)?;
+        if move_map.insert(move_id.clone(), definition).is_some() {
+            return Err(MoveFileError {
+                line: table.line,
+                message: format!("move '{}' is defined twice", move_id),
+            });
+        }
+    }
+    Ok(move_map)
+}
+
+pub fn load_move_file(
+    path: &Path,
+    preset: Option<Leniency>,
+) -> Result<HashMap<MoveId, MoveDefinition>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse_move_list(&contents, preset).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn default_move_map(preset: Option<Leniency>) -> HashMap<MoveId, MoveDefinition> {
+    parse_move_list(DEFAULT_MOVES, preset).expect("built-in move list must be valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::static_types::{ButtonNotation, NumericalNotation};
+
+    fn parse(contents: &str) -> Result<HashMap<MoveId, MoveDefinition>, MoveFileError> {
+        parse_move_list(contents, None)
+    }
+
+    #[test]
+    fn test_default_move_list_loads() {
+        let move_map = default_move_map(None);
+        let dp = &move_map[&MoveId("DP".to_string())];
+        assert_eq!(
+            dp.directions,
+            vec![
+                NumericalNotation::Six,
+                NumericalNotation::Two,
+                NumericalNotation::Three,
+            ]
+        );
+        for name in [
+            "QCF",
+            "QCB",
+            "HCF",
+            "HCB",
+            "DOWNDOWN",
+            "DOUBLE_QCF",
+            "DOUBLE_QCB",
+            "ARCSUPER",
+        ] {
+            assert!(move_map.contains_key(&MoveId(name.to_string())), "{}", name);
+        }
+        assert_eq!(move_map[&MoveId("360".to_string())].rotations, 1);
+        assert_eq!(move_map[&MoveId("720".to_string())].rotations, 2);
+    }
+
+    #[test]
+    fn test_parse() {
+        let move_map = parse(
+            r#"
+            # Guile
+            [[move]] # Sonic Boom
+            name = "Sonic Boom"
+            motion = "[4]6"
+            button = "LP" # any strength in game
+            preset = "sf6"
+            step_gap = 10
+            charge_frames = 40
+            negative_edge = true
+            "#,
+        )
+        .unwrap();
+
+        let sonic_boom = &move_map[&MoveId("Sonic Boom".to_string())];
+        assert_eq!(sonic_boom.charge, Some(NumericalNotation::Four));
+        assert_eq!(sonic_boom.button, ButtonNotation::LP);
+        assert_eq!(
+            sonic_boom.leniency,
+            Leniency {
+                step_gap: 10,
+                ..Leniency::preset("sf6").unwrap()
+            }
+        );
+        assert_eq!(sonic_boom.charge_frames, 40);
+        assert!(sonic_boom.negative_edge);
+    }
+
+    #[test]
+    fn test_parse_move_list_errors() {
+        let error = parse("name = \"DP\"").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!(error.message.starts_with("unknown key 'name'"));
+
+        let error = parse("[[move]]\nname = \"DP\"\nbutton = \"P\"").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert_eq!(error.message, "move is missing 'motion'");
+
+        let error = parse("[[move]]\nname = \"DP\"\nmotion = \"6X3\"\nbutton = \"P\"").unwrap_err();
+        assert!(error.message.contains("position 1"));
+
+        let error =
+            parse("[[move]]\nname = \"DP\"\nmotion = \"623\"\nbutton = \"P\"\nstep_gap = \"7\"")
+                .unwrap_err();
+        assert_eq!(error.line, 5);
+
+        let error = parse("[[move]]\nname = 'DP'").unwrap_err();
+        assert_eq!(
+            error.message,
+            "single-quoted strings are not supported, values are double-quoted strings, \
+integers or booleans"
+        );
+    }
+
+    #[test]
+    fn test_preset_precedence() {
+        let contents = r#"
+            preset = "kof"
+
+            [[move]]
+            name = "QCF"
+            motion = "236"
+            button = "P"
+
+            [[move]]
+            name = "DP"
+            motion = "623"
+            button = "P"
+            preset = "strive"
+            button_window = 2
+            "#;
+        let kof = Leniency::preset("kof").unwrap();
+        let sf6 = Leniency::preset("sf6").unwrap();
+        let strive = Leniency::preset("strive").unwrap();
+
+        let move_map = parse(contents).unwrap();
+        assert_eq!(move_map[&MoveId("QCF".to_string())].leniency, kof);
+        assert_eq!(
+            move_map[&MoveId("DP".to_string())].leniency,
+            Leniency {
+                button_window: 2,
+                ..strive
+            }
+        );
+
+        let move_map = parse_move_list(contents, Some(sf6)).unwrap();
+        assert_eq!(move_map[&MoveId("QCF".to_string())].leniency, sf6);
+        assert_eq!(
+            move_map[&MoveId("DP".to_string())].leniency.step_gap,
+            strive.step_gap
+        );
+
+        let error = parse("preset = \"tekken\"").unwrap_err();
+        assert!(error.message.starts_with("unknown preset 'tekken'"));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::static_types::{ButtonNotation, Leniency, MoveDefinition, NumericalNotation};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub const DEFAULT_CHARGE_FRAMES: usize = 45;
+
+// Longer names first so "HS" is not read as an unknown "H"
+const BUTTON_NAMES: [(&str, ButtonNotation); 11] = [
+    ("HS", ButtonNotation::HS),
+    ("LP", ButtonNotation::LP),
+    ("MP", ButtonNotation::MP),
+    ("HP", ButtonNotation::HP),
+    ("LK", ButtonNotation::LK),
+    ("MK", ButtonNotation::MK),
+    ("HK", ButtonNotation::HK),
+    ("P", ButtonNotation::P),
+    ("K", ButtonNotation::K),
+    ("S", ButtonNotation::S),
+    ("D", ButtonNotation::D),
+];
+
+// Game button written on its own, e.g. in a button profile
+pub fn parse_button(name: &str) -> Option<ButtonNotation> {
+    BUTTON_NAMES
+        .into_iter()
+        .find(|(button_name, _)| button_name.eq_ignore_ascii_case(name))
+        .map(|(_, button)| button)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotationError {
+    // Character index of the offending input, or the input length at end of input
+    pub position: usize,
+    pub found: Option<char>,
+    pub expected: &'static str,
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.found {
+            Some(found) => write!(
+                f,
+                "unexpected '{}' at position {}, expected {}",
+                found, self.position, self.expected
+            ),
+            None => write!(
+                f,
+                "unexpected end of input at position {}, expected {}",
+                self.position, self.expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+struct NotationReader<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: usize,
+}
+
+impl NotationReader<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.chars.next();
+        if next.is_some() {
+            self.position += 1;
+        }
+        next
+    }
+
+    fn error(&mut self, expected: &'static str) -> NotationError {
+        NotationError {
+            position: self.position,
+            found: self.peek(),
+            expected,
+        }
+    }
+
+    fn direction(&mut self) -> Result<NumericalNotation, NotationError> {
+        match self.peek().and_then(NumericalNotation::from_digit) {
+            Some(direction) => {
+                self.next();
+                Ok(direction)
+            }
+            None => Err(self.error("a direction (1-9)")),
+        }
+    }
+
+    fn expect(&mut self, expected_char: char, expected: &'static str) -> Result<(), NotationError> {
+        if self.peek() == Some(expected_char) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    fn button(&mut self, rest: &str) -> Result<ButtonNotation, NotationError> {
+        for (name, button) in BUTTON_NAMES {
+            if rest.starts_with(name) {
+                for _ in 0..name.len() {
+                    self.next();
+                }
+                return Ok(button);
+            }
+        }
+        Err(self.error("a direction (1-9) or a button (P, K, S, HS, D, LP..HK)"))
+    }
+}
+
+// Parses fighting game notation such as "623P", "41236HS", "[4]6P" or
+// "360P" into a move definition: optional charge or rotation, directions,
+// then the button
+pub fn parse_motion(notation: &str) -> Result<MoveDefinition, NotationError> {
+    let mut reader = NotationReader {
+        chars: notation.chars().peekable(),
+        position: 0,
+    };
+
+    for (prefix, rotations) in [("360", 1), ("720", 2)] {
+        if let Some(rest) = notation.strip_prefix(prefix) {
+            for _ in 0..prefix.len() {
+                reader.next();
+            }
+            let button = reader.button(rest)?;
+            if reader.peek().is_some() {
+                return Err(reader.error("end of input after the button"));
+            }
+            return Ok(MoveDefinition {
+                charge: None,
+                charge_frames: 0,
+                directions: Vec::new(),
+                rotations,
+                button,
+                leniency: Leniency::default(),
+                priority: 0,
+                negative_edge: false,
+            });
+        }
+    }
+
+    let mut charge = None;
+    if reader.peek() == Some('[') {
+        reader.next();
+        charge = Some(reader.direction()?);
+        reader.expect(']', "']' to close the charge segment")?;
+    }
+
+    let mut directions = vec![reader.direction()?];
+    while let Some(direction) = reader.peek().and_then(NumericalNotation::from_digit) {
+        reader.next();
+        directions.push(direction);
+    }
+
+    let rest: String = notation.chars().skip(reader.position).collect();
+    let button = reader.button(&rest)?;
+
+    if reader.peek().is_some() {
+        return Err(reader.error("end of input after the button"));
+    }
+
+    Ok(MoveDefinition {
+        charge,
+        charge_frames: if charge.is_some() {
+            DEFAULT_CHARGE_FRAMES
+        } else {
+            0
+        },
+        directions,
+        rotations: 0,
+        button,
+        leniency: Leniency::default(),
+        priority: 0,
+        negative_edge: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_motions() {
+        let dp = parse_motion("623P").unwrap();
+        assert_eq!(dp.charge, None);
+        assert_eq!(
+            dp.directions,
+            vec![
+                NumericalNotation::Six,
+                NumericalNotation::Two,
+                NumericalNotation::Three,
+            ]
+        );
+        assert_eq!(dp.button, ButtonNotation::P);
+
+        let double_qcf = parse_motion("236236K").unwrap();
+        assert_eq!(double_qcf.directions.len(), 6);
+        assert_eq!(double_qcf.button, ButtonNotation::K);
+
+        let hcf = parse_motion("41236HS").unwrap();
+        assert_eq!(hcf.directions.len(), 5);
+        assert_eq!(hcf.button, ButtonNotation::HS);
+
+        assert_eq!(parse_motion("214LK").unwrap().button, ButtonNotation::LK);
+    }
+
+    #[test]
+    fn test_parse_charge_motion() {
+        let sonic_boom = parse_motion("[4]6P").unwrap();
+        assert_eq!(sonic_boom.charge, Some(NumericalNotation::Four));
+        assert_eq!(sonic_boom.directions, vec![NumericalNotation::Six]);
+        assert_eq!(
+            sonic_boom.sequence(),
+            vec![NumericalNotation::Four, NumericalNotation::Six]
+        );
+    }
+
+    #[test]
+    fn test_parse_rotation_motion() {
+        let grab = parse_motion("360P").unwrap();
+        assert_eq!(grab.rotations, 1);
+        assert!(grab.directions.is_empty());
+        assert_eq!(parse_motion("720K").unwrap().rotations, 2);
+
+        let error = parse_motion("3606P").unwrap_err();
+        assert_eq!(error.position, 3);
+        assert_eq!(error.found, Some('6'));
+    }
+
+    #[test]
+    fn test_parse_errors_point_to_offending_character() {
+        let error = parse_motion("6X3P").unwrap_err();
+        assert_eq!(error.position, 1);
+        assert_eq!(error.found, Some('X'));
+
+        let error = parse_motion("623").unwrap_err();
+        assert_eq!(error.position, 3);
+        assert_eq!(error.found, None);
+
+        let error = parse_motion("[46P").unwrap_err();
+        assert_eq!(error.position, 2);
+        assert_eq!(error.found, Some('6'));
+
+        let error = parse_motion("623PP").unwrap_err();
+        assert_eq!(error.position, 4);
+
+        let error = parse_motion("P").unwrap_err();
+        assert_eq!(error.position, 0);
+        assert_eq!(error.found, Some('P'));
+
+        assert_eq!(
+            parse_motion("60P").unwrap_err().to_string(),
+            "unexpected '0' at position 1, expected a direction (1-9) or a button (P, K, S, HS, D, LP..HK)"
+        );
+    }
+}
@@ -0,0 +1,596 @@
+use crate::button_profile::ButtonProfile;
+use crate::input_explainer::{check_move_sequence, diagnose_attempt};
+use crate::input_reader::{
+    apply_event, calculate_position, detect_edges, detect_event_edges, is_attack_pressed,
+    stick_direction,
+};
+use crate::static_types::{
+    AttackButton, ButtonEdge, ButtonNotation, ButtonState, ButtonsStates, Diagnosis, Edge,
+    FRAME_DURATION, Facing, GlobalState, HistoryMode, InputButton, InputChange, InputEvent,
+    MatchResult, MoveDefinition, MoveId, NumericalNotation, RealTimings, SocdMode, StickSettings,
+    SubFrameTap, frame_of,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub const MAX_PLAYERS: usize = 2;
+const MAX_SUB_FRAME_TAPS: usize = 8;
+
+// Everything tracked for one controller: its buttons, history and moves
+#[derive(Clone)]
+pub struct Player {
+    pub device: usize,
+    pub profile: ButtonProfile,
+    current_state: ButtonsStates,
+    previous_state: ButtonsStates,
+    // Frame each attack button was last pressed on, and the last frame a
+    // move came out, so a release does not repeat the move of its press
+    pressed_on: HashMap<AttackButton, u64>,
+    last_move_frame: Option<u64>,
+    // Frames that reached the history, which moves count their windows in
+    played_frames: u64,
+    // Every button transition with its original timestamp, oldest first,
+    // covering about as long as the direction history
+    transitions: Vec<InputEvent>,
+    // Transitions applied since the last frame was sampled, and the buttons
+    // after each directional one
+    frame_events: Vec<InputEvent>,
+    intermediate_states: Vec<(Duration, ButtonsStates)>,
+    // What the renderer shows for this player
+    pub view: GlobalState,
+    // What happened during the last frame
+    pub report: FrameReport,
+}
+
+// How a player's inputs are read into the history
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerSettings {
+    // What left+right and up+down resolve to
+    pub socd_mode: SocdMode,
+    pub stick: StickSettings,
+    pub history_mode: HistoryMode,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings {
+            socd_mode: SocdMode::Neutral,
+            stick: StickSettings::default(),
+            history_mode: HistoryMode::GameAccurate,
+        }
+    }
+}
+
+// A direction the history moved to, as held and as read on the 1P side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionChange {
+    pub direction: NumericalNotation,
+    pub as_1p: NumericalNotation,
+    pub time: Duration,
+}
+
+// Changes of one update, for tools that follow them as they happen rather
+// than reading the whole view
+#[derive(Debug, Clone, Default)]
+pub struct FrameReport {
+    pub directions: Vec<DirectionChange>,
+    // Attack button edges with the game button they perform, if mapped
+    pub button_edges: Vec<(ButtonEdge, Option<ButtonNotation>)>,
+    pub detected: Option<MatchResult>,
+    pub missed: Option<Diagnosis>,
+}
+
+impl Player {
+    // Odd players start on the 1P side and even ones on the 2P side
+    pub fn new(
+        player: usize,
+        device: usize,
+        profile: ButtonProfile,
+        settings: &PlayerSettings,
+    ) -> Player {
+        let mut view = GlobalState::new(player, settings.socd_mode);
+        view.history_mode = settings.history_mode;
+        if player.is_multiple_of(2) {
+            view.facing = Facing::Left;
+        }
+        Player {
+            device,
+            profile,
+            current_state: ButtonsStates::default(),
+            previous_state: ButtonsStates::default(),
+            pressed_on: HashMap::new(),
+            last_move_frame: None,
+            played_frames: 0,
+            transitions: Vec::new(),
+            frame_events: Vec::new(),
+            intermediate_states: Vec::new(),
+            view,
+            report: FrameReport::default(),
+        }
+    }
+
+    // Starts over with empty histories, keeping the buttons held and the
+    // player's setup
+    pub fn reset(&mut self) {
+        let mut view = GlobalState::new(self.view.player, self.view.socd_mode);
+        view.facing = self.view.facing;
+        view.history_mode = self.view.history_mode;
+        view.paused = self.view.paused;
+        view.notice = self.view.notice.take();
+        self.view = view;
+        self.pressed_on.clear();
+        self.last_move_frame = None;
+        self.transitions.clear();
+    }
+
+    // Drops a frame while paused: buttons keep following the controller but
+    // nothing reaches the history
+    pub fn skip_frame(&mut self) {
+        self.report = FrameReport::default();
+        self.previous_state = self.current_state.clone();
+        self.frame_events.clear();
+        self.intermediate_states.clear();
+    }
+
+    pub fn apply_event(&mut self, event: &InputEvent) {
+        apply_event(event, &mut self.current_state);
+
+        if let InputChange::Button(button, ButtonState::Released) = event.change
+            && let Some(press) = self.transitions.iter().rev().find(|transition| {
+                transition.change == InputChange::Button(button, ButtonState::Pressed)
+            })
+            && frame_of(press.time) == frame_of(event.time)
+        {
+            self.view.sub_frame_taps.push(SubFrameTap {
+                button,
+                frame: frame_of(event.time),
+                held: event.time.saturating_sub(press.time),
+            });
+            if self.view.sub_frame_taps.len() > MAX_SUB_FRAME_TAPS {
+                self.view.sub_frame_taps.remove(0);
+            }
+        }
+        self.transitions.push(*event);
+        self.frame_events.push(*event);
+        // Transitions reported at the same time happened together
+        if is_directional(event) {
+            if self
+                .intermediate_states
+                .last()
+                .is_some_and(|(time, _)| *time == event.time)
+            {
+                self.intermediate_states.pop();
+            }
+            self.intermediate_states
+                .push((event.time, self.current_state.clone()));
+        }
+    }
+
+    // Samples the buttons for `frame`, extends the history and looks for a
+    // move on the attack buttons that changed
+    pub fn update(
+        &mut self,
+        frame: u64,
+        settings: &PlayerSettings,
+        move_map: &HashMap<MoveId, MoveDefinition>,
+        history_length: usize,
+    ) {
+        // Lossless mode also sees buttons pressed and released within the frame
+        let button_edges = match settings.history_mode {
+            HistoryMode::GameAccurate => {
+                detect_edges(&self.previous_state, &self.current_state, frame)
+            }
+            HistoryMode::Lossless => {
+                detect_event_edges(&self.previous_state, &self.frame_events, frame)
+            }
+        };
+        self.previous_state = self.current_state.clone();
+        let report = &mut self.report;
+        *report = FrameReport::default();
+
+        // Directions reached this frame with the time they started at. Game
+        // accurate mode only sees the end of the frame, lossless mode every
+        // direction the transitions went through.
+        let frame_start = FRAME_DURATION * frame as u32;
+        let mut samples: Vec<(NumericalNotation, Duration)> = Vec::new();
+        if settings.history_mode == HistoryMode::Lossless {
+            for (time, state) in &self.intermediate_states {
+                samples.push((sample_position(state, settings), *time));
+            }
+        }
+        let last_direction_change = self
+            .frame_events
+            .iter()
+            .rev()
+            .find(|event| is_directional(event))
+            .map_or(frame_start, |event| event.time);
+        samples.push((
+            sample_position(&self.current_state, settings),
+            last_direction_change,
+        ));
+        samples.dedup_by_key(|(position, _)| *position);
+
+        let played_frame = self.played_frames;
+        self.played_frames += 1;
+        let view = &mut self.view;
+        for (position, time) in samples {
+            view.current_position = position;
+            // A direction carried over from the previous entry keeps its start
+            let direction_time = match (view.raw_history.last(), view.direction_times.last()) {
+                (Some(&previous), Some(&previous_time)) if previous == position => previous_time,
+                _ => {
+                    report.directions.push(DirectionChange {
+                        direction: position,
+                        as_1p: position.facing(view.facing),
+                        time,
+                    });
+                    time
+                }
+            };
+            view.direction_times.push(direction_time);
+            view.raw_history.push(position);
+            view.position_history.push(position.facing(view.facing));
+            view.history_frames.push(played_frame);
+            while view
+                .history_frames
+                .first()
+                .is_some_and(|&first| played_frame - first >= history_length as u64)
+            {
+                view.position_history.remove(0);
+                view.raw_history.remove(0);
+                view.direction_times.remove(0);
+                view.history_frames.remove(0);
+            }
+        }
+        view.stick = self.current_state.stick;
+        view.held_directions = self.current_state.held_directions();
+
+        view.attack_pressed = is_attack_pressed(&self.current_state);
+        report.button_edges = button_edges
+            .iter()
+            .map(|button_edge| (*button_edge, self.profile.game_button(button_edge.button)))
+            .collect();
+        for button_edge in &button_edges {
+            if button_edge.edge == Edge::Press {
+                self.pressed_on
+                    .insert(button_edge.button, button_edge.frame);
+            }
+        }
+        // Game buttons pressed this frame, and released ones whose press did
+        // not already give a move
+        let pressed: Vec<ButtonNotation> = button_edges
+            .iter()
+            .filter(|button_edge| button_edge.edge == Edge::Press)
+            .filter_map(|button_edge| self.profile.game_button(button_edge.button))
+            .collect();
+        let released: Vec<ButtonNotation> = button_edges
+            .iter()
+            .filter(|button_edge| {
+                button_edge.edge == Edge::Release
+                    && self.last_move_frame < self.pressed_on.get(&button_edge.button).copied()
+            })
+            .filter_map(|button_edge| self.profile.game_button(button_edge.button))
+            .collect();
+
+        // When the buttons changed, from the transitions of this frame
+        let edge_time = |edge: Edge| {
+            let state = match edge {
+                Edge::Press => ButtonState::Pressed,
+                Edge::Release => ButtonState::Released,
+            };
+            self.frame_events
+                .iter()
+                .find(|event| {
+                    matches!(event.change, InputChange::Button(InputButton::Attack(_), s) if s == state)
+                })
+                .map_or(frame_start, |event| event.time)
+        };
+
+        if !pressed.is_empty() {
+            match check_move_sequence(
+                &view.position_history,
+                &view.history_frames,
+                move_map,
+                &pressed,
+                Edge::Press,
+            ) {
+                Some(mut match_result) => {
+                    match_result.real_timings =
+                        real_timings(&match_result, &view.direction_times, edge_time(Edge::Press));
+                    report.detected = Some(match_result.clone());
+                    view.last_successful_move.push(match_result);
+                    view.last_failed_attempt = None;
+                    self.last_move_frame = Some(frame);
+                }
+                None => {
+                    view.last_failed_attempt = diagnose_attempt(
+                        &view.position_history,
+                        &view.history_frames,
+                        move_map,
+                        &pressed,
+                    );
+                    report.missed = view.last_failed_attempt.clone();
+                }
+            }
+        } else if !released.is_empty()
+            && let Some(mut match_result) = check_move_sequence(
+                &view.position_history,
+                &view.history_frames,
+                move_map,
+                &released,
+                Edge::Release,
+            )
+        {
+            match_result.real_timings = real_timings(
+                &match_result,
+                &view.direction_times,
+                edge_time(Edge::Release),
+            );
+            report.detected = Some(match_result.clone());
+            view.last_successful_move.push(match_result);
+            view.last_failed_attempt = None;
+            self.last_move_frame = Some(frame);
+        }
+
+        self.frame_events.clear();
+        self.intermediate_states.clear();
+        let kept_since = frame_start.saturating_sub(FRAME_DURATION * history_length as u32);
+        self.transitions.retain(|event| event.time >= kept_since);
+    }
+}
+
+fn is_directional(event: &InputEvent) -> bool {
+    !matches!(event.change, InputChange::Button(InputButton::Attack(_), _))
+}
+
+// Direction given by the buttons and the stick, the stick taking over
+// whenever it is out of its deadzone
+fn sample_position(buttons_state: &ButtonsStates, settings: &PlayerSettings) -> NumericalNotation {
+    match stick_direction(buttons_state.stick, &settings.stick) {
+        NumericalNotation::Five => calculate_position(buttons_state, settings.socd_mode),
+        direction => direction,
+    }
+}
+
+// Timings of a detected move from when each of its directions started and
+// when the button changed
+fn real_timings(
+    match_result: &MatchResult,
+    direction_times: &[Duration],
+    button_time: Duration,
+) -> Option<RealTimings> {
+    let step_times: Vec<Duration> = match_result
+        .steps
+        .iter()
+        .map(|step| direction_times.get(step.history_index).copied())
+        .collect::<Option<_>>()?;
+    Some(RealTimings {
+        step_gaps: step_times
+            .windows(2)
+            .map(|pair| pair[1].saturating_sub(pair[0]))
+            .collect(),
+        button_delay: button_time.saturating_sub(*step_times.last()?),
+        motion: button_time.saturating_sub(*step_times.first()?),
+    })
+}
+
+// Plays `recording` frame by frame with the built-in move list, each event
+// going to the player of its device, and calls `on_frame` after the updates
+#[cfg(test)]
+pub(crate) fn play_recording(
+    recording: &str,
+    players: &mut [Player],
+    settings: &PlayerSettings,
+    frames: u64,
+    mut on_frame: impl FnMut(u64, &[Player]),
+) {
+    use crate::input_source::{InputSource, ScriptedSource, parse_recording};
+    use crate::move_loader::default_move_map;
+
+    let move_map = default_move_map(None);
+    let events = parse_recording(recording).unwrap();
+    let mut source = ScriptedSource::new(events, FRAME_DURATION);
+    for frame in 0..frames {
+        for event in source.poll() {
+            if let Some(player) = players
+                .iter_mut()
+                .find(|player| player.device == event.device)
+            {
+                player.apply_event(&event);
+            }
+        }
+        for player in players.iter_mut() {
+            player.update(frame, settings, &move_map, 120);
+        }
+        on_frame(frame, players);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_source::{InputSource, ScriptedSource, parse_recording};
+    use crate::move_loader::default_move_map;
+
+    // The view of a single player 1 after playing `recording`
+    fn play_alone(recording: &str, settings: &PlayerSettings, frames: u64) -> GlobalState {
+        let mut players = [Player::new(1, 0, ButtonProfile::default(), settings)];
+        play_recording(recording, &mut players, settings, frames, |_, _| {});
+        let [player] = players;
+        player.view
+    }
+
+    #[test]
+    fn test_players_only_see_their_device() {
+        let settings = PlayerSettings::default();
+        // Device 0 does a fireball while device 1 only holds down
+        let recording = "0 down press\n0 down press 1\n20 right press\n40 down release\n\
+                         41 west press\n55 right release";
+        let mut players = [
+            Player::new(1, 0, ButtonProfile::default(), &settings),
+            Player::new(2, 1, ButtonProfile::default(), &settings),
+        ];
+        play_recording(recording, &mut players, &settings, 5, |_, _| {});
+
+        let moves = &players[0].view.last_successful_move;
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].move_id, MoveId("QCF".to_string()));
+        assert_eq!(moves[0].button, ButtonNotation::LP);
+        assert!(players[1].view.last_successful_move.is_empty());
+        assert_eq!(players[1].view.current_position, NumericalNotation::Two);
+    }
+
+    #[test]
+    fn test_motions_mirror_on_the_2p_side() {
+        let settings = PlayerSettings::default();
+        // 4 2 1 + LP, a DP for a player facing left
+        let recording =
+            "0 left press\n20 left release\n20 down press\n40 left press\n41 west press";
+        let mut players = [Player::new(2, 0, ButtonProfile::default(), &settings)];
+        play_recording(recording, &mut players, &settings, 3, |_, _| {});
+        let [player] = players;
+
+        assert_eq!(player.view.facing, Facing::Left);
+        assert_eq!(
+            player.view.last_successful_move[0].move_id,
+            MoveId("DP".to_string())
+        );
+        assert_eq!(
+            player.view.raw_history.last(),
+            Some(&NumericalNotation::One)
+        );
+        assert_eq!(
+            player.view.position_history.last(),
+            Some(&NumericalNotation::Three)
+        );
+    }
+
+    #[test]
+    fn test_pause_and_reset() {
+        let settings = PlayerSettings::default();
+        let move_map = default_move_map(None);
+        let events = parse_recording("0 down press\n20 right press").unwrap();
+        let mut source = ScriptedSource::new(events, FRAME_DURATION);
+
+        let mut player = Player::new(2, 0, ButtonProfile::default(), &settings);
+        for event in source.poll() {
+            player.apply_event(&event);
+        }
+        player.update(0, &settings, &move_map, 120);
+        // The press of right is applied but the paused frame is not recorded
+        for event in source.poll() {
+            player.apply_event(&event);
+        }
+        player.skip_frame();
+        assert_eq!(player.view.raw_history, vec![NumericalNotation::Two]);
+        assert!(player.report.directions.is_empty());
+
+        player.reset();
+        assert!(player.view.raw_history.is_empty());
+        assert_eq!(player.view.facing, Facing::Left);
+        player.update(2, &settings, &move_map, 120);
+        assert_eq!(player.view.raw_history, vec![NumericalNotation::Three]);
+    }
+
+    #[test]
+    fn test_timestamps_give_real_timings_and_taps() {
+        // A 4ms tap of up inside frame 0, then 236 + LP with the button 5ms
+        // after forward
+        let view = play_alone(
+            "2 up press\n6 up release\n20 down press\n40 right press\n\
+             55 down release\n60 west press",
+            &PlayerSettings::default(),
+            4,
+        );
+
+        assert_eq!(
+            view.sub_frame_taps,
+            vec![SubFrameTap {
+                button: InputButton::Up,
+                frame: 0,
+                held: Duration::from_millis(4),
+            }]
+        );
+        assert!(!view.raw_history.contains(&NumericalNotation::Eight));
+
+        let match_result = &view.last_successful_move[0];
+        assert_eq!(match_result.move_id, MoveId("QCF".to_string()));
+        assert_eq!(
+            match_result.real_timings,
+            Some(RealTimings {
+                step_gaps: vec![Duration::from_millis(20), Duration::from_millis(15)],
+                button_delay: Duration::from_millis(5),
+                motion: Duration::from_millis(40),
+            })
+        );
+    }
+
+    #[test]
+    fn test_lossless_mode_keeps_directions_between_frames() {
+        // 6, then 2 and 3 within the next frame, then LP: a real game only
+        // sees 6-3
+        let recording =
+            "0 right press\n18 right release\n18 down press\n25 right press\n40 west press";
+        let run = |history_mode| {
+            let settings = PlayerSettings {
+                history_mode,
+                ..PlayerSettings::default()
+            };
+            play_alone(recording, &settings, 3)
+        };
+
+        let sampled = run(HistoryMode::GameAccurate);
+        assert_eq!(
+            sampled.raw_history,
+            vec![
+                NumericalNotation::Six,
+                NumericalNotation::Three,
+                NumericalNotation::Three
+            ]
+        );
+        assert!(sampled.last_successful_move.is_empty());
+
+        let lossless = run(HistoryMode::Lossless);
+        assert_eq!(
+            lossless.raw_history,
+            vec![
+                NumericalNotation::Six,
+                NumericalNotation::Two,
+                NumericalNotation::Three,
+                NumericalNotation::Three
+            ]
+        );
+        assert_eq!(
+            lossless.last_successful_move[0].move_id,
+            MoveId("DP".to_string())
+        );
+        // Windows count frames, not the extra entries
+        assert_eq!(lossless.last_successful_move[0].total_frames, 3);
+        assert_eq!(lossless.last_successful_move[0].step_gaps, vec![1, 1]);
+    }
+
+    #[test]
+    fn test_lossless_mode_sees_sub_frame_button_taps() {
+        // 236 then LP tapped for 3ms, never held at the end of a frame
+        let recording =
+            "0 down press\n20 right press\n40 down release\n45 west press\n48 west release";
+        let run = |history_mode| {
+            let settings = PlayerSettings {
+                history_mode,
+                ..PlayerSettings::default()
+            };
+            play_alone(recording, &settings, 4)
+        };
+
+        assert!(
+            run(HistoryMode::GameAccurate)
+                .last_successful_move
+                .is_empty()
+        );
+        let lossless = run(HistoryMode::Lossless);
+        assert_eq!(
+            lossless.last_successful_move[0].move_id,
+            MoveId("QCF".to_string())
+        );
+    }
+}
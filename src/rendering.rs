@@ -1,8 +1,17 @@
-use crate::static_types::{GlobalState, NumericalNotation};
+use crate::static_types::{
+    Edge, FRAME_DURATION, GlobalState, InputButton, MatchResult, NumericalNotation, ReplayStatus,
+    SubFrameTap, UiCommand,
+};
 use ratatui::{
     Frame,
-    crossterm::event::{self as ratEvent, Event as RatEvent},
-    layout::{Alignment, Constraint, Layout},
+    crossterm::{
+        event::{
+            self as ratEvent, Event as RatEvent, KeyCode, KeyEvent, KeyEventKind,
+            KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+        },
+        execute,
+    },
+    layout::{Alignment, Constraint, Layout, Rect},
     style::Color,
     symbols::Marker,
     widgets::{
@@ -10,50 +19,75 @@ use ratatui::{
         canvas::{Canvas, Circle, Line},
     },
 };
-use std::sync::mpsc::Receiver;
+use std::io::stdout;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::thread;
 use std::time::{Duration, Instant};
 
-pub fn render_grid(render_rx: Receiver<GlobalState>) -> Result<(), String> {
+// Page Up and Page Down move a replay by five seconds
+const SEEK_FRAMES: i64 = 300;
+// How often the terminal reader checks whether drawing stopped
+const TERMINAL_POLL_TIMEOUT: Duration = Duration::from_millis(50);
+
+// Key presses are forwarded to `key_tx` with the instant they were read when
+// the keyboard is the input device; `keyboard_enhancement` asks the terminal
+// to report key releases.
+// Tab switches sides, Backspace resets, Enter pauses, F2 switches the history
+// mode and Esc quits. Replays also step with Left and Right, seek with Page Up
+// and Page Down and change speed with Up and Down.
+pub fn render_grid(
+    render_rx: Receiver<Vec<GlobalState>>,
+    command_tx: Sender<UiCommand>,
+    key_tx: Option<Sender<(Instant, KeyEvent)>>,
+    keyboard_enhancement: bool,
+) -> Result<(), String> {
     if let Err(e) = color_eyre::install() {
         eprintln!("Failed to install color_eyre: {}", e);
         return Err(e.to_string());
     };
     let mut terminal = ratatui::init();
-    let mut current_position = NumericalNotation::Five;
-
-    let mut current_state: GlobalState = GlobalState {
-        current_position: current_position,
-        attack_pressed: false,
-        position_history: Vec::new(),
-        close_requested: false,
-        last_successful_move: vec![],
+    if keyboard_enhancement
+        && let Err(e) = execute!(
+            stdout(),
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+        )
+    {
+        ratatui::restore();
+        return Err(format!("Failed to enable key release events: {}", e));
+    }
+    let stop_reading = Arc::new(AtomicBool::new(false));
+    let reader_handle = {
+        let stop_reading = Arc::clone(&stop_reading);
+        thread::spawn(move || read_terminal(&stop_reading, &command_tx, key_tx.as_ref()))
     };
+    let mut current_state: Vec<GlobalState> = Vec::new();
 
+    // Runs until the input loop is gone, which Esc also ends
+    let mut result = Ok(());
     loop {
         let frame_start = Instant::now();
 
-        if let Ok(new_state) = render_rx.try_recv() {
-            current_state = new_state;
+        let mut exit_requested = false;
+        loop {
+            match render_rx.try_recv() {
+                Ok(new_state) => current_state = new_state,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    exit_requested = true;
+                    break;
+                }
+            }
         }
 
-        if let Ok(CompletedFrame) = terminal.draw(|f| run_drawing(f, &current_state)) {
-        } else {
-            eprintln!("Failed to draw frame");
-            return Err("Failed to draw frame".to_string());
+        if let Err(e) = terminal.draw(|f| run_drawing(f, &current_state)) {
+            result = Err(format!("Failed to draw frame: {}", e));
+            break;
         }
 
-        if ratEvent::poll(Duration::from_millis(0)).unwrap_or(false) {
-            if let Ok(RatEvent::Key(key)) = ratEvent::read() {
-                match key.code {
-                    ratEvent::KeyCode::Esc => {
-                        println!("Exiting...");
-                        // current_state.close_requested = true;
-                        break;
-                    }
-                    _ => {}
-                }
-            }
+        if exit_requested {
+            break;
         }
 
         let frame_time = frame_start.elapsed();
@@ -63,8 +97,59 @@ pub fn render_grid(render_rx: Receiver<GlobalState>) -> Result<(), String> {
         }
     }
 
+    stop_reading.store(true, Ordering::Relaxed);
+    let _ = reader_handle.join();
+    if keyboard_enhancement {
+        let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+    }
     ratatui::restore();
-    Ok(())
+    result
+}
+
+// Reads the terminal on its own thread so keys are stamped when they arrive,
+// not when the next frame is drawn
+fn read_terminal(
+    stop_reading: &AtomicBool,
+    command_tx: &Sender<UiCommand>,
+    key_tx: Option<&Sender<(Instant, KeyEvent)>>,
+) {
+    while !stop_reading.load(Ordering::Relaxed) {
+        match ratEvent::poll(TERMINAL_POLL_TIMEOUT) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(_) => break,
+        }
+        let Ok(RatEvent::Key(key)) = ratEvent::read() else {
+            continue;
+        };
+        let read_at = Instant::now();
+        let command = match key.code {
+            KeyCode::Esc => Some(UiCommand::Quit),
+            KeyCode::Tab => Some(UiCommand::SwitchSides),
+            KeyCode::Backspace => Some(UiCommand::Reset),
+            KeyCode::Enter => Some(UiCommand::TogglePause),
+            KeyCode::F(2) => Some(UiCommand::SwitchHistoryMode),
+            KeyCode::Right => Some(UiCommand::StepForward),
+            KeyCode::Left => Some(UiCommand::StepBack),
+            KeyCode::PageDown => Some(UiCommand::Seek(SEEK_FRAMES)),
+            KeyCode::PageUp => Some(UiCommand::Seek(-SEEK_FRAMES)),
+            KeyCode::Up => Some(UiCommand::Faster),
+            KeyCode::Down => Some(UiCommand::Slower),
+            _ => None,
+        };
+        match command {
+            Some(command) => {
+                if key.kind == KeyEventKind::Press {
+                    let _ = command_tx.send(command);
+                }
+            }
+            None => {
+                if let Some(key_tx) = key_tx {
+                    let _ = key_tx.send((read_at, key));
+                }
+            }
+        }
+    }
 }
 
 fn get_coordinates(position: &NumericalNotation) -> (f64, f64) {
@@ -97,11 +182,26 @@ fn get_coordinates_pairs(positions: &[NumericalNotation]) -> Vec<((f64, f64), (f
         .collect()
 }
 
-fn run_drawing(frame: &mut Frame, state: &GlobalState) {
+// One column per player, side by side
+fn run_drawing(frame: &mut Frame, states: &[GlobalState]) {
+    if states.is_empty() {
+        frame.render_widget(
+            Paragraph::new("Waiting for input...").alignment(Alignment::Center),
+            frame.area(),
+        );
+        return;
+    }
+    let columns = Layout::horizontal(vec![Constraint::Fill(1); states.len()]).split(frame.area());
+    for (state, area) in states.iter().zip(columns.iter()) {
+        draw_player(frame, *area, state);
+    }
+}
+
+fn draw_player(frame: &mut Frame, area: Rect, state: &GlobalState) {
     let [left_area, right_area] =
         Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
             .margin(1)
-            .areas(frame.area());
+            .areas(area);
 
     let [top_left_area, bottom_left_area] =
         Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(left_area);
@@ -110,10 +210,19 @@ fn run_drawing(frame: &mut Frame, state: &GlobalState) {
         Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
             .areas(right_area);
     let circle_coordinates = get_coordinates(&state.current_position);
+    // Raw stick position on the same scale as the quantized directions
+    let stick_coordinates = (state.stick.0 as f64 * 3.1, state.stick.1 as f64 * 3.1);
 
-    let lines_pairs = get_coordinates_pairs(&state.position_history);
+    let lines_pairs = get_coordinates_pairs(&state.raw_history);
 
-    let block = Block::default().title("Input map");
+    let block = Block::default().title(format!(
+        "P{} ({}) Input map  {}, {} history{}",
+        state.player,
+        state.facing.side(),
+        describe_socd(state),
+        state.history_mode.name(),
+        if state.paused { " [paused]" } else { "" }
+    ));
     let inner_area = block.inner(top_left_area);
 
     frame.render_widget(block, top_left_area);
@@ -121,17 +230,25 @@ fn run_drawing(frame: &mut Frame, state: &GlobalState) {
     let canvas = Canvas::default()
         .paint(|ctx| {
             ctx.draw(&Circle {
-                x: circle_coordinates.0 as f64,
-                y: circle_coordinates.1 as f64,
+                x: circle_coordinates.0,
+                y: circle_coordinates.1,
                 radius: 1.5,
                 color: Color::Red,
             });
+            if state.stick != (0.0, 0.0) {
+                ctx.draw(&Circle {
+                    x: stick_coordinates.0,
+                    y: stick_coordinates.1,
+                    radius: 0.5,
+                    color: Color::Yellow,
+                });
+            }
             for (start, end) in &lines_pairs {
                 ctx.draw(&Line {
-                    x1: start.0 as f64,
-                    y1: start.1 as f64,
-                    x2: end.0 as f64,
-                    y2: end.1 as f64,
+                    x1: start.0,
+                    y1: start.1,
+                    x2: end.0,
+                    y2: end.1,
                     color: Color::Green,
                 });
             }
@@ -163,13 +280,167 @@ fn run_drawing(frame: &mut Frame, state: &GlobalState) {
         let block = Block::default().title("No Attack");
         frame.render_widget(block, top_right_area);
     };
+    let last_move = match state.last_successful_move.last() {
+        Some(match_result) => describe_match(match_result),
+        None => "-".to_string(),
+    };
+    let last_miss = match &state.last_failed_attempt {
+        Some(diagnosis) => diagnosis.to_string(),
+        None => "-".to_string(),
+    };
     frame.render_widget(
         Paragraph::new(format!(
-            "Last successful move: {:?}",
-            state.last_successful_move
+            "{}Last successful move: {}\nLast miss: {}\nHeld: {}\nAs 1P: {}\nSub-frame taps: {}\nTick jitter: {:.2}ms avg, {:.2}ms max\n{}",
+            describe_replay(state.replay),
+            last_move,
+            last_miss,
+            recent_directions(&state.raw_history),
+            recent_directions(&state.position_history),
+            describe_taps(&state.sub_frame_taps),
+            milliseconds(state.tick_jitter.average),
+            milliseconds(state.tick_jitter.max),
+            state.notice.as_deref().unwrap_or_default()
         ))
         .alignment(Alignment::Center)
-        .wrap(ratatui::widgets::Wrap { trim: true }),
+        .wrap(Wrap { trim: true }),
         bottom_left_area,
     );
+
+    let move_log = state
+        .last_successful_move
+        .iter()
+        .rev()
+        .map(|match_result| {
+            format!(
+                "{} {} ({}f){}",
+                match_result.move_id,
+                match_result.button,
+                match_result.total_frames,
+                release_tag(match_result)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    frame.render_widget(
+        Paragraph::new(move_log).block(Block::default().title("Move log")),
+        bottom_right_area,
+    );
+}
+
+// Last directions of a history with repeats collapsed, e.g. "5 4 1 2"
+fn recent_directions(history: &[NumericalNotation]) -> String {
+    let mut directions: Vec<u8> = Vec::new();
+    for &direction in history {
+        if directions.last() != Some(&(direction as u8)) {
+            directions.push(direction as u8);
+        }
+    }
+    let start = directions.len().saturating_sub(8);
+    directions[start..]
+        .iter()
+        .map(|direction| direction.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Raw directions next to the cleaned one, e.g. "raw ←→↓ = 2 (SOCD last)"
+fn describe_socd(state: &GlobalState) -> String {
+    let raw: String = state
+        .held_directions
+        .iter()
+        .map(|direction| match direction {
+            InputButton::Up => '↑',
+            InputButton::Down => '↓',
+            InputButton::Left => '←',
+            InputButton::Right => '→',
+            InputButton::Attack(_) => '?',
+        })
+        .collect();
+    format!(
+        "raw {} = {} (SOCD {})",
+        if raw.is_empty() { "-" } else { &raw },
+        state.current_position as u8,
+        state.socd_mode.name()
+    )
+}
+
+// e.g. "DP HP: 6 -3f- 2 -1f- 3, button +0f, 5f total"
+fn describe_match(match_result: &MatchResult) -> String {
+    let mut steps = String::new();
+    for (step_idx, step) in match_result.steps.iter().enumerate() {
+        if step_idx > 0 {
+            steps.push_str(&format!(" -{}f- ", match_result.step_gaps[step_idx - 1]));
+        }
+        steps.push_str(&(step.direction as u8).to_string());
+    }
+    let charge = match match_result.charge_frames {
+        Some(charge_frames) => format!("charge {}f, ", charge_frames),
+        None => String::new(),
+    };
+    let real = match &match_result.real_timings {
+        Some(real_timings) => format!(
+            " (motion {:.1}ms, button +{:.1}ms)",
+            milliseconds(real_timings.motion),
+            milliseconds(real_timings.button_delay)
+        ),
+        None => String::new(),
+    };
+    format!(
+        "{} {}: {}{}, button +{}f, {}f total{}{}",
+        match_result.move_id,
+        match_result.button,
+        charge,
+        steps,
+        match_result.button_delay,
+        match_result.total_frames,
+        real,
+        release_tag(match_result)
+    )
+}
+
+// e.g. "Replay 1.50s / 12.00s (frame 90/720), 0.5x, paused\n"
+fn describe_replay(replay: Option<ReplayStatus>) -> String {
+    let Some(replay) = replay else {
+        return String::new();
+    };
+    format!(
+        "Replay {:.2}s / {:.2}s (frame {}/{}), {}x{}\n",
+        (FRAME_DURATION * replay.frame as u32).as_secs_f64(),
+        (FRAME_DURATION * replay.length as u32).as_secs_f64(),
+        replay.frame,
+        replay.length,
+        replay.speed,
+        if replay.playing { "" } else { ", paused" }
+    )
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Latest taps first, e.g. "west 3.2ms (f812), down 9.8ms (f640)"
+fn describe_taps(taps: &[SubFrameTap]) -> String {
+    if taps.is_empty() {
+        return "-".to_string();
+    }
+    taps.iter()
+        .rev()
+        .take(3)
+        .map(|tap| {
+            format!(
+                "{} {:.1}ms (f{})",
+                tap.button.name(),
+                milliseconds(tap.held),
+                tap.frame
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn release_tag(match_result: &MatchResult) -> &'static str {
+    match match_result.edge {
+        Edge::Press => "",
+        Edge::Release => " on release",
+    }
 }
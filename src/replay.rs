@@ -0,0 +1,117 @@
+use crate::input_source::{InputSource, RecordedSource};
+use crate::static_types::ReplayStatus;
+use std::path::Path;
+
+// Playback speeds, from an eighth to eight times real time
+pub const REPLAY_SPEEDS: [f64; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const REAL_TIME: usize = 3;
+
+// Playback controls over a recorded session: pausing, speed, and how many
+// frames of `source` each real frame plays. The frames themselves are played
+// by polling `source` like any other input.
+pub struct Replay {
+    pub source: RecordedSource,
+    pub playing: bool,
+    speed: usize,
+    // Part of a frame carried over to the next tick when slowed down
+    carried_over: f64,
+}
+
+impl Replay {
+    pub fn new(source: RecordedSource) -> Replay {
+        Replay {
+            source,
+            playing: true,
+            speed: REAL_TIME,
+            carried_over: 0.0,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Replay, String> {
+        RecordedSource::from_file(path).map(Replay::new)
+    }
+
+    // Frames played so far, the next frame to play
+    pub fn frame(&self) -> u64 {
+        self.source.frame()
+    }
+
+    pub fn length(&self) -> u64 {
+        self.source.length()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.source.finished()
+    }
+
+    pub fn speed(&self) -> f64 {
+        REPLAY_SPEEDS[self.speed]
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(REPLAY_SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    // How many frames to play for one real frame, none once paused or over
+    pub fn frames_due(&mut self) -> u64 {
+        if !self.playing || self.finished() {
+            self.carried_over = 0.0;
+            return 0;
+        }
+        self.carried_over += self.speed();
+        let frames = self.carried_over.floor();
+        self.carried_over -= frames;
+        (frames as u64).min(self.length() - self.frame())
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        ReplayStatus {
+            frame: self.frame(),
+            length: self.length(),
+            speed: self.speed(),
+            playing: self.playing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_source::parse_session;
+
+    fn replay(contents: &str) -> Replay {
+        Replay::new(RecordedSource::new(parse_session(contents).unwrap()))
+    }
+
+    #[test]
+    fn test_replay_plays_to_the_end() {
+        let mut replay = replay("0 down press\n40 down release\nframe 3 reset");
+        assert_eq!(replay.frames_due(), 1);
+        while !replay.finished() {
+            replay.source.poll();
+        }
+        assert_eq!(replay.status().frame, 4);
+        assert_eq!(replay.frames_due(), 0);
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        let mut replay = replay("0 down press\n1000 down release");
+
+        replay.slower();
+        assert_eq!(replay.speed(), 0.5);
+        assert_eq!(replay.frames_due(), 0);
+        assert_eq!(replay.frames_due(), 1);
+
+        replay.faster();
+        replay.faster();
+        assert_eq!(replay.frames_due(), 2);
+
+        replay.playing = false;
+        assert_eq!(replay.frames_due(), 0);
+    }
+}
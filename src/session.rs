@@ -0,0 +1,389 @@
+use crate::config::AppConfig;
+use crate::session_recorder::SessionRecorder;
+use rust_input_speed::button_profile::{ButtonProfile, load_profile, profile_path, save_profile};
+use rust_input_speed::headless::report_lines;
+use rust_input_speed::input_source::{InputSource, RecordedSource};
+use rust_input_speed::player::{MAX_PLAYERS, Player};
+use rust_input_speed::replay::Replay;
+use rust_input_speed::static_types::{
+    InputEvent, MoveDefinition, MoveId, SessionCommand, UiCommand,
+};
+use std::collections::HashMap;
+
+// Frames between the keyframes of a replay, five seconds
+const KEYFRAME_INTERVAL: u64 = 300;
+
+// The players and what they need to take in events
+pub struct Session {
+    pub config: AppConfig,
+    pub move_map: HashMap<MoveId, MoveDefinition>,
+    pub history_length: usize,
+    pub explicit_profile: Option<ButtonProfile>,
+    pub players: Vec<Player>,
+    // Frames are skipped while paused
+    pub paused: bool,
+    // Whether players are on the opposite side of the one they start on,
+    // and how much of that the viewer of a replay asked for with Tab
+    pub sides_switched: bool,
+    pub viewer_switched: bool,
+    pub recorder: Option<SessionRecorder>,
+    // Why recording stopped, if it failed
+    pub recording_error: Option<String>,
+    // The state of a replay every `KEYFRAME_INTERVAL` frames from its start,
+    // for going back without playing it all again
+    keyframes: Vec<Keyframe>,
+}
+
+// A replay and the players as they were at the start of a frame
+struct Keyframe {
+    source: RecordedSource,
+    players: Vec<Player>,
+    paused: bool,
+    sides_switched: bool,
+    viewer_switched: bool,
+}
+
+impl Session {
+    pub fn new(
+        config: AppConfig,
+        move_map: HashMap<MoveId, MoveDefinition>,
+        history_length: usize,
+        explicit_profile: Option<ButtonProfile>,
+        recorder: Option<SessionRecorder>,
+    ) -> Session {
+        Session {
+            config,
+            move_map,
+            history_length,
+            explicit_profile,
+            players: Vec::new(),
+            paused: false,
+            sides_switched: false,
+            viewer_switched: false,
+            recorder,
+            recording_error: None,
+            keyframes: Vec::new(),
+        }
+    }
+
+    // Plays `frame` with what `source` gives for it, its commands before its
+    // events, live input and recordings alike
+    pub fn play_frame(&mut self, source: &mut dyn InputSource, frame: u64) {
+        let events = source.poll();
+        for command in source.commands() {
+            self.apply_command(frame, command);
+        }
+        for event in &events {
+            self.apply_event(event, source);
+        }
+        self.update(frame);
+    }
+
+    // Hands an event to the player of its device, adding a player for a new
+    // device while there is room
+    fn apply_event(&mut self, event: &InputEvent, source: &dyn InputSource) {
+        self.record(|recorder| recorder.record_event(event));
+        let player_idx = match self
+            .players
+            .iter()
+            .position(|player| player.device == event.device)
+        {
+            Some(player_idx) => player_idx,
+            None if self.players.len() < MAX_PLAYERS => {
+                let controller_name = source.controller_name(event.device);
+                let (profile, notice) = player_profile(
+                    &self.config,
+                    &self.explicit_profile,
+                    controller_name.as_deref(),
+                );
+                let mut player = Player::new(
+                    self.players.len() + 1,
+                    event.device,
+                    profile,
+                    &self.config.player_settings(),
+                );
+                player.view.notice = notice;
+                if self.sides_switched {
+                    player.view.facing = player.view.facing.flipped();
+                }
+                self.record(|recorder| recorder.record_player(&player, controller_name.as_deref()));
+                self.players.push(player);
+                self.players.len() - 1
+            }
+            None => return,
+        };
+        self.players[player_idx].apply_event(event);
+    }
+
+    // Sides, reset and pause, recorded so a replay of the session goes
+    // through the same frames
+    pub fn apply_command(&mut self, frame: u64, command: SessionCommand) {
+        self.record(|recorder| recorder.record_command(frame, command));
+        match command {
+            SessionCommand::SwitchSides => {
+                self.sides_switched = !self.sides_switched;
+                for player in &mut self.players {
+                    player.view.facing = player.view.facing.flipped();
+                }
+            }
+            SessionCommand::Reset => self.players.iter_mut().for_each(Player::reset),
+            SessionCommand::Pause => self.paused = true,
+            SessionCommand::Resume => self.paused = false,
+        }
+    }
+
+    fn update(&mut self, frame: u64) {
+        for player in &mut self.players {
+            if self.paused {
+                player.skip_frame();
+            } else {
+                player.update(
+                    frame,
+                    &self.config.player_settings(),
+                    &self.move_map,
+                    self.history_length,
+                );
+            }
+            record(&mut self.recorder, &mut self.recording_error, |recorder| {
+                recorder.record_frame(frame, player)
+            });
+        }
+    }
+
+    // Goes back to the last keyframe at or before `frame`, the players on the
+    // side the viewer left them and in the current history mode
+    fn rewind(&mut self, replay: &mut Replay, frame: u64) {
+        let Some(keyframe) = self
+            .keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| keyframe.source.frame() <= frame)
+        else {
+            return;
+        };
+        replay.source = keyframe.source.clone();
+        self.players = keyframe.players.clone();
+        self.paused = keyframe.paused;
+        self.sides_switched = keyframe.sides_switched;
+        if keyframe.viewer_switched != self.viewer_switched {
+            self.sides_switched = !self.sides_switched;
+            for player in &mut self.players {
+                player.view.facing = player.view.facing.flipped();
+            }
+        }
+    }
+
+    pub fn reports(&self, frame: u64) -> Vec<String> {
+        self.players
+            .iter()
+            .flat_map(|player| report_lines(player.view.player, frame, &player.report))
+            .collect()
+    }
+
+    pub fn record(&mut self, write: impl FnOnce(&mut SessionRecorder) -> Result<(), String>) {
+        record(&mut self.recorder, &mut self.recording_error, write);
+    }
+}
+
+// Runs `write` on the recorder, recording stops at the first error
+fn record(
+    recorder: &mut Option<SessionRecorder>,
+    recording_error: &mut Option<String>,
+    write: impl FnOnce(&mut SessionRecorder) -> Result<(), String>,
+) {
+    if let Some(active) = recorder
+        && let Err(e) = write(active)
+    {
+        *recorder = None;
+        *recording_error = Some(e);
+    }
+}
+
+// Plays the next frame of a replay and returns its number, keeping a
+// keyframe every `KEYFRAME_INTERVAL` frames the first time through
+pub fn play_replay_frame(session: &mut Session, replay: &mut Replay) -> u64 {
+    let frame = replay.frame();
+    if frame == session.keyframes.len() as u64 * KEYFRAME_INTERVAL {
+        session.keyframes.push(Keyframe {
+            source: replay.source.clone(),
+            players: session.players.clone(),
+            paused: session.paused,
+            sides_switched: session.sides_switched,
+            viewer_switched: session.viewer_switched,
+        });
+    }
+    session.play_frame(&mut replay.source, frame);
+    frame
+}
+
+// Pause, speed, stepping and seeking in a replay
+pub fn control_replay(session: &mut Session, replay: &mut Replay, command: UiCommand) {
+    let target = match command {
+        UiCommand::TogglePause => {
+            replay.playing = !replay.playing;
+            return;
+        }
+        UiCommand::Faster => {
+            replay.faster();
+            return;
+        }
+        UiCommand::Slower => {
+            replay.slower();
+            return;
+        }
+        UiCommand::StepForward => {
+            replay.playing = false;
+            replay.frame() + 1
+        }
+        UiCommand::StepBack => {
+            replay.playing = false;
+            replay.frame().saturating_sub(1)
+        }
+        UiCommand::Seek(frames) => replay.frame().saturating_add_signed(frames),
+        UiCommand::Reset => 0,
+        _ => return,
+    };
+    let target = target.min(replay.length());
+    // Players only go forward, going back plays the replay again from the
+    // keyframe before the target
+    if target < replay.frame() {
+        session.rewind(replay, target);
+    }
+    while replay.frame() < target {
+        play_replay_frame(session, replay);
+    }
+}
+
+// Button profile of a newly connected controller with the --map changes
+// applied, saved when asked to, and a note on where it came from
+fn player_profile(
+    config: &AppConfig,
+    explicit_profile: &Option<ButtonProfile>,
+    controller_name: Option<&str>,
+) -> (ButtonProfile, Option<String>) {
+    let saved_path = controller_name.map(profile_path);
+    let (mut profile, mut notice) = match (explicit_profile, &saved_path) {
+        (Some(profile), _) => (profile.clone(), None),
+        (None, Some(path)) if path.exists() => match load_profile(path) {
+            Ok(profile) => (profile, Some(format!("Profile {}", path.display()))),
+            Err(e) => (ButtonProfile::default(), Some(e)),
+        },
+        _ => (ButtonProfile::default(), None),
+    };
+    for (physical, game_button) in &config.mappings {
+        profile.set(*physical, *game_button);
+    }
+
+    if config.save_profile {
+        let saved = match (controller_name, &saved_path) {
+            (Some(name), Some(path)) => save_profile(path, &profile, name)
+                .map(|()| format!("Saved profile to {}", path.display())),
+            _ => Err("No controller name to save a button profile under".to_string()),
+        };
+        notice = Some(saved.unwrap_or_else(|e| e));
+    }
+    (profile, notice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_input_speed::input_source::{
+        InputMessage, ThreadedSource, parse_recording, parse_session,
+    };
+    use rust_input_speed::move_loader::default_move_map;
+    use rust_input_speed::static_types::frame_of;
+    use std::sync::mpsc;
+
+    fn session(recorder: Option<SessionRecorder>) -> Session {
+        Session::new(
+            AppConfig::default(),
+            default_move_map(None),
+            120,
+            None,
+            recorder,
+        )
+    }
+
+    #[test]
+    fn test_replay_goes_through_the_live_frames() {
+        // 236P with the sides switched on frame 3. The press right at the
+        // end of frame 0 comes in time, every other event only after its
+        // frame was played.
+        let events = parse_recording(
+            "16.6666 down press\n30 right press\n33.4 down release\n40 west press\n90 west release",
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "rust_input_speed_session_{}.txt",
+            std::process::id()
+        ));
+        let config = AppConfig::default();
+        let recorder = SessionRecorder::create(&path, &config, &default_move_map(None)).unwrap();
+        let mut live = session(Some(recorder));
+        let (input_tx, input_rx) = mpsc::channel::<InputMessage>();
+        let mut source = ThreadedSource::new(input_rx);
+        input_tx.send(InputMessage::Event(events[0])).unwrap();
+        let mut live_reports = Vec::new();
+        for frame in 0..8 {
+            if frame == 3 {
+                live.apply_command(frame, SessionCommand::SwitchSides);
+            }
+            live.play_frame(&mut source, frame);
+            live_reports.push(live.reports(frame));
+            for event in events[1..]
+                .iter()
+                .filter(|event| frame_of(event.time) == frame)
+            {
+                input_tx.send(InputMessage::Event(*event)).unwrap();
+            }
+        }
+        live.record(SessionRecorder::flush);
+        assert!(live.recording_error.is_none());
+
+        let mut source = RecordedSource::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut replayed = session(None);
+        let mut replay_reports = Vec::new();
+        for frame in 0..8 {
+            replayed.play_frame(&mut source, frame);
+            replay_reports.push(replayed.reports(frame));
+        }
+
+        assert!(
+            live_reports
+                .concat()
+                .iter()
+                .any(|line| line.contains("\"move\""))
+        );
+        assert_eq!(replay_reports, live_reports);
+        assert!(replayed.sides_switched);
+    }
+
+    #[test]
+    fn test_replay_goes_back_from_a_keyframe() {
+        let recording = parse_session(
+            "0 up press\n50 up release\n6650 down press\n6680 right press\n6700 down release\n\
+             6750 west press\nframe 500 switch_sides\n11000 right release",
+        )
+        .unwrap();
+        let mut replay = Replay::new(RecordedSource::new(recording));
+        let mut session = session(None);
+        let mut reports = Vec::new();
+        while !replay.finished() {
+            let frame = play_replay_frame(&mut session, &mut replay);
+            reports.push(session.reports(frame));
+        }
+        assert_eq!((replay.frame(), session.keyframes.len()), (660, 3));
+        assert!(reports[404].iter().any(|line| line.contains("\"move\"")));
+
+        control_replay(&mut session, &mut replay, UiCommand::Seek(-255));
+        assert_eq!(replay.frame(), 405);
+        assert_eq!(session.reports(404), reports[404]);
+        assert!(!session.sides_switched);
+        control_replay(&mut session, &mut replay, UiCommand::Seek(200));
+        assert_eq!(session.reports(604), reports[604]);
+        assert!(session.sides_switched);
+        assert_eq!(session.keyframes.len(), 3);
+    }
+}
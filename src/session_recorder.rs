@@ -0,0 +1,184 @@
+use crate::config::{AppConfig, InputBackend};
+use rust_input_speed::player::Player;
+use rust_input_speed::static_types::{
+    ATTACK_BUTTONS, ButtonState, FRAME_DURATION, InputChange, InputEvent, MoveDefinition, MoveId,
+    SessionCommand,
+};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Bumped whenever the meaning of a line changes
+pub const SESSION_VERSION: u32 = 1;
+
+// Writes a practice session as a recording `--replay` can play back. The
+// header, the players and the direction of each frame are `#` comments, so
+// only the button and stick events and the commands are replayed.
+pub struct SessionRecorder {
+    file: BufWriter<File>,
+}
+
+impl SessionRecorder {
+    pub fn create(
+        path: &Path,
+        config: &AppConfig,
+        move_map: &HashMap<MoveId, MoveDefinition>,
+    ) -> Result<SessionRecorder, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let mut recorder = SessionRecorder {
+            file: BufWriter::new(file),
+        };
+        recorder.write(&format_header(config, move_map))?;
+        Ok(recorder)
+    }
+
+    pub fn record_player(
+        &mut self,
+        player: &Player,
+        controller_name: Option<&str>,
+    ) -> Result<(), String> {
+        self.write(&format_player(player, controller_name))
+    }
+
+    pub fn record_event(&mut self, event: &InputEvent) -> Result<(), String> {
+        self.write(&format_event(event))
+    }
+
+    // Sides switched, the players reset or the session paused on `frame`
+    pub fn record_command(&mut self, frame: u64, command: SessionCommand) -> Result<(), String> {
+        self.write(&format!("frame {} {}", frame, command.name()))
+    }
+
+    // Directions the player's history moved to during `frame`
+    pub fn record_frame(&mut self, frame: u64, player: &Player) -> Result<(), String> {
+        for change in &player.report.directions {
+            self.write(&format!(
+                "# frame {} P{} {}",
+                frame, player.view.player, change.direction as u8
+            ))?;
+        }
+        Ok(())
+    }
+
+    // Called every frame, so an interrupted session loses at most one frame
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.file
+            .flush()
+            .map_err(|e| format!("Failed to write the session: {}", e))
+    }
+
+    fn write(&mut self, lines: &str) -> Result<(), String> {
+        writeln!(self.file, "{}", lines).map_err(|e| format!("Failed to write the session: {}", e))
+    }
+}
+
+pub fn format_header(config: &AppConfig, move_map: &HashMap<MoveId, MoveDefinition>) -> String {
+    let input = match &config.input {
+        InputBackend::Gamepad => "gamepad".to_string(),
+        InputBackend::Keyboard => "keyboard".to_string(),
+        InputBackend::Replay(path) => format!("replay {}", path.display()),
+    };
+    let moves = match &config.moves_path {
+        Some(path) => path.display().to_string(),
+        None => "default".to_string(),
+    };
+    let mut move_names: Vec<&str> = move_map.keys().map(|move_id| move_id.0.as_str()).collect();
+    move_names.sort();
+    format!(
+        "# rust_input_speed session {}\n# tick_rate: {}\n# input: {}\n# socd: {}\n\
+# history_mode: {}\n# moves: {} ({})",
+        SESSION_VERSION,
+        (1.0 / FRAME_DURATION.as_secs_f64()).round(),
+        input,
+        config.socd_mode.name(),
+        config.history_mode.name(),
+        moves,
+        move_names.join(", ")
+    )
+}
+
+// e.g. "# player 1: device 0, controller Xbox Controller, profile west=LP ..."
+pub fn format_player(player: &Player, controller_name: Option<&str>) -> String {
+    let profile = ATTACK_BUTTONS
+        .into_iter()
+        .filter_map(|physical| {
+            let game_button = player.profile.game_button(physical)?;
+            Some(format!("{}={}", physical.name(), game_button))
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "# player {}: device {}, controller {}, profile {}",
+        player.view.player,
+        player.device,
+        controller_name.unwrap_or("unknown"),
+        profile
+    )
+}
+
+// A recording line, in milliseconds down to the nanosecond so the event
+// plays on the same frame it was applied on, the device left out for device 0
+pub fn format_event(event: &InputEvent) -> String {
+    let change = match event.change {
+        InputChange::Button(button, state) => format!(
+            "{} {}",
+            button.name(),
+            match state {
+                ButtonState::Pressed => "press",
+                ButtonState::Released => "release",
+            }
+        ),
+        InputChange::Stick(axis, value) => format!("{} {}", axis.name(), value),
+    };
+    let nanos = event.time.as_nanos();
+    let millis = format!("{}.{:06}", nanos / 1_000_000, nanos % 1_000_000);
+    match event.device {
+        0 => format!("{} {}", millis, change),
+        device => format!("{} {} {}", millis, change, device),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_input_speed::input_source::parse_recording;
+    use rust_input_speed::move_loader::default_move_map;
+    use rust_input_speed::static_types::{AttackButton, InputButton, StickAxis};
+    use std::time::Duration;
+
+    #[test]
+    fn test_events_round_trip_through_recordings() {
+        let events = vec![
+            InputEvent {
+                time: Duration::from_micros(1_250),
+                device: 0,
+                change: InputChange::Button(InputButton::Down, ButtonState::Pressed),
+            },
+            InputEvent {
+                time: FRAME_DURATION - Duration::from_nanos(1),
+                device: 1,
+                change: InputChange::Stick(StickAxis::X, -0.75),
+            },
+            InputEvent {
+                time: Duration::from_millis(40),
+                device: 0,
+                change: InputChange::Button(
+                    InputButton::Attack(AttackButton::RightTrigger),
+                    ButtonState::Released,
+                ),
+            },
+        ];
+        let config = AppConfig::default();
+        let mut contents = format_header(&config, &default_move_map(None));
+        for event in &events {
+            contents.push('\n');
+            contents.push_str(&format_event(event));
+        }
+
+        assert!(contents.starts_with("# rust_input_speed session 1\n# tick_rate: 60\n"));
+        assert_eq!(format_event(&events[1]), "16.666666 stick_x -0.75 1");
+        assert_eq!(parse_recording(&contents), Ok(events));
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum PossibleCoordinates {
@@ -20,61 +21,796 @@ pub enum NumericalNotation {
     Nine = 9,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+impl NumericalNotation {
+    pub fn from_digit(digit: char) -> Option<NumericalNotation> {
+        match digit {
+            '1' => Some(NumericalNotation::One),
+            '2' => Some(NumericalNotation::Two),
+            '3' => Some(NumericalNotation::Three),
+            '4' => Some(NumericalNotation::Four),
+            '5' => Some(NumericalNotation::Five),
+            '6' => Some(NumericalNotation::Six),
+            '7' => Some(NumericalNotation::Seven),
+            '8' => Some(NumericalNotation::Eight),
+            '9' => Some(NumericalNotation::Nine),
+            _ => None,
+        }
+    }
+
+    // Horizontal and vertical stick components, -1 for back/down
+    pub fn coordinates(self) -> (i8, i8) {
+        match self {
+            NumericalNotation::One => (-1, -1),
+            NumericalNotation::Two => (0, -1),
+            NumericalNotation::Three => (1, -1),
+            NumericalNotation::Four => (-1, 0),
+            NumericalNotation::Five => (0, 0),
+            NumericalNotation::Six => (1, 0),
+            NumericalNotation::Seven => (-1, 1),
+            NumericalNotation::Eight => (0, 1),
+            NumericalNotation::Nine => (1, 1),
+        }
+    }
+
+    // Whether holding this direction also holds `other`, so 1 charges both 4 and 2
+    pub fn includes(self, other: NumericalNotation) -> bool {
+        let (horizontal, vertical) = self.coordinates();
+        let (other_horizontal, other_vertical) = other.coordinates();
+        (other_horizontal == 0 || other_horizontal == horizontal)
+            && (other_vertical == 0 || other_vertical == vertical)
+    }
+
+    // The direction as written for a player facing right, so 4 read on the
+    // 2P side is forward
+    pub fn facing(self, facing: Facing) -> NumericalNotation {
+        if facing == Facing::Right {
+            return self;
+        }
+        match self {
+            NumericalNotation::One => NumericalNotation::Three,
+            NumericalNotation::Three => NumericalNotation::One,
+            NumericalNotation::Four => NumericalNotation::Six,
+            NumericalNotation::Six => NumericalNotation::Four,
+            NumericalNotation::Seven => NumericalNotation::Nine,
+            NumericalNotation::Nine => NumericalNotation::Seven,
+            direction => direction,
+        }
+    }
+}
+
+// Side of the screen the player's character faces, from the 1P side it
+// faces right
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facing {
+    Right,
+    Left,
+}
+
+impl Facing {
+    pub fn flipped(self) -> Facing {
+        match self {
+            Facing::Right => Facing::Left,
+            Facing::Left => Facing::Right,
+        }
+    }
+
+    pub fn side(self) -> &'static str {
+        match self {
+            Facing::Right => "1P side",
+            Facing::Left => "2P side",
+        }
+    }
+}
+
+// Requests from the user interface to the input loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UiCommand {
+    SwitchSides,
+    // Clear the histories and move logs of every player, or restart a replay
+    Reset,
+    // Stop or resume processing frames, or playing a replay
+    TogglePause,
+    // Switch between the game accurate and lossless history modes
+    SwitchHistoryMode,
+    // Replay controls: play one frame more or less and pause, jump by a
+    // number of frames, change the speed
+    StepForward,
+    StepBack,
+    Seek(i64),
+    Faster,
+    Slower,
+    Quit,
+}
+
+// What the user did to a session besides pressing buttons, kept in
+// recordings so a replay goes through the same frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionCommand {
+    SwitchSides,
+    Reset,
+    Pause,
+    Resume,
+}
+
+pub const SESSION_COMMANDS: [SessionCommand; 4] = [
+    SessionCommand::SwitchSides,
+    SessionCommand::Reset,
+    SessionCommand::Pause,
+    SessionCommand::Resume,
+];
+
+impl SessionCommand {
+    pub fn name(self) -> &'static str {
+        match self {
+            SessionCommand::SwitchSides => "switch_sides",
+            SessionCommand::Reset => "reset",
+            SessionCommand::Pause => "pause",
+            SessionCommand::Resume => "resume",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SessionCommand> {
+        SESSION_COMMANDS
+            .into_iter()
+            .find(|command| command.name() == name)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, Default)]
 pub enum ButtonState {
     Pressed,
+    #[default]
     Released,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Copy)]
-pub enum Moves {
-    DP,
-    QCF,
-    QCB,
-    ARCSUPER,
-    HCB,
+// Name of a move as given in the move list file
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MoveId(pub String);
+
+impl fmt::Display for MoveId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Attack buttons as written in move notation, covering both the
+// four-button (P/K/S/HS/D) and six-button (LP..HK) conventions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ButtonNotation {
+    P,
+    K,
+    S,
+    HS,
+    D,
+    LP,
+    MP,
+    HP,
+    LK,
+    MK,
+    HK,
 }
 
-#[derive(Debug, Clone)]
+impl ButtonNotation {
+    // Whether pressing `pressed` performs a move written with this button, a
+    // generic P or K being performed by any punch or kick strength
+    pub fn matches(self, pressed: ButtonNotation) -> bool {
+        match self {
+            ButtonNotation::P => matches!(
+                pressed,
+                ButtonNotation::P | ButtonNotation::LP | ButtonNotation::MP | ButtonNotation::HP
+            ),
+            ButtonNotation::K => matches!(
+                pressed,
+                ButtonNotation::K | ButtonNotation::LK | ButtonNotation::MK | ButtonNotation::HK
+            ),
+            _ => self == pressed,
+        }
+    }
+}
+
+impl fmt::Display for ButtonNotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// Input buffers of a move, all in frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leniency {
+    // Maximum gap between two consecutive steps of the motion
+    pub step_gap: usize,
+    // Maximum time from the first to the last step of the motion
+    pub motion_window: usize,
+    // How long after the last direction the button may still be pressed
+    pub button_window: usize,
+}
+
+pub const LENIENCY_PRESETS: [(&str, Leniency); 4] = [
+    // Original behaviour of the trainer: 7 frames between steps, motions
+    // limited by the old 30 frame history, button on the last direction
+    (
+        "default",
+        Leniency {
+            step_gap: 7,
+            motion_window: 30,
+            button_window: 0,
+        },
+    ),
+    // Approximations of each game's buffers, tune with per-move overrides
+    (
+        "sf6",
+        Leniency {
+            step_gap: 11,
+            motion_window: 30,
+            button_window: 5,
+        },
+    ),
+    (
+        "strive",
+        Leniency {
+            step_gap: 10,
+            motion_window: 30,
+            button_window: 8,
+        },
+    ),
+    (
+        "kof",
+        Leniency {
+            step_gap: 8,
+            motion_window: 24,
+            button_window: 3,
+        },
+    ),
+];
+
+impl Leniency {
+    pub fn preset(name: &str) -> Option<Leniency> {
+        LENIENCY_PRESETS
+            .iter()
+            .find(|(preset_name, _)| preset_name.eq_ignore_ascii_case(name))
+            .map(|(_, leniency)| *leniency)
+    }
+}
+
+impl Default for Leniency {
+    fn default() -> Self {
+        LENIENCY_PRESETS[0].1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveDefinition {
+    pub charge: Option<NumericalNotation>,
+    // Frames the charge direction has to be held before the release
+    pub charge_frames: usize,
+    pub directions: Vec<NumericalNotation>,
+    // Full stick rotations for command grabs ("360" is 1, "720" is 2),
+    // matched from any starting direction instead of `directions`
+    pub rotations: usize,
+    pub button: ButtonNotation,
+    pub leniency: Leniency,
+    // Higher priority wins when several moves match the same history
+    pub priority: usize,
+    // Whether releasing the button after the motion also performs the move
+    pub negative_edge: bool,
+}
+
+impl MoveDefinition {
+    // Full direction sequence to look for in the history, charge included
+    pub fn sequence(&self) -> Vec<NumericalNotation> {
+        self.charge
+            .iter()
+            .chain(self.directions.iter())
+            .copied()
+            .collect()
+    }
+
+    // Number of directions a player goes through to perform the motion
+    pub fn motion_length(&self) -> usize {
+        if self.rotations > 0 {
+            self.rotations * 8
+        } else {
+            self.sequence().len()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedStep {
+    pub direction: NumericalNotation,
+    pub history_index: usize,
+}
+
+// Everything known about a detected move, frames counted in history entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchResult {
+    pub move_id: MoveId,
+    // Motion steps in input order; for 360 and 720 these are the cardinal directions
+    pub steps: Vec<MatchedStep>,
+    // Frames between each step and the next one
+    pub step_gaps: Vec<usize>,
+    // How long the charge was held, for charge moves
+    pub charge_frames: Option<usize>,
+    // Frames between the last direction of the motion and the button press
+    pub button_delay: usize,
+    // Frames from the start of the motion, charge included, to the button press
+    pub total_frames: usize,
+    // Game button that performed the move
+    pub button: ButtonNotation,
+    // Whether the move came out on a button press or on a release
+    pub edge: Edge,
+    // Same timings measured from the input timestamps, when they are known
+    pub real_timings: Option<RealTimings>,
+}
+
+// Move timings in real time rather than frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RealTimings {
+    pub step_gaps: Vec<Duration>,
+    pub button_delay: Duration,
+    // From the first direction of the motion to the button
+    pub motion: Duration,
+}
+
+// Why a button press did not produce the move the player was going for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureReason {
+    MissingSteps {
+        matched: Vec<NumericalNotation>,
+        missing: Vec<NumericalNotation>,
+    },
+    StepTooSlow {
+        from: NumericalNotation,
+        to: NumericalNotation,
+        gap: usize,
+        limit: usize,
+    },
+    MotionTooSlow {
+        frames: usize,
+        limit: usize,
+    },
+    ButtonLate {
+        late_by: usize,
+    },
+    ChargeTooShort {
+        held: usize,
+        needed: usize,
+    },
+}
+
+fn join_directions(directions: &[NumericalNotation]) -> String {
+    directions
+        .iter()
+        .map(|direction| (*direction as u8).to_string())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::MissingSteps { matched, missing } => write!(
+                f,
+                "got {} but missing {}",
+                join_directions(matched),
+                join_directions(missing)
+            ),
+            FailureReason::StepTooSlow {
+                from,
+                to,
+                gap,
+                limit,
+            } => write!(
+                f,
+                "too slow between {} and {} ({}f > {}f)",
+                *from as u8, *to as u8, gap, limit
+            ),
+            FailureReason::MotionTooSlow { frames, limit } => {
+                write!(f, "motion too slow ({}f > {}f)", frames, limit)
+            }
+            FailureReason::ButtonLate { late_by } => {
+                write!(f, "button pressed {}f after motion window closed", late_by)
+            }
+            FailureReason::ChargeTooShort { held, needed } => {
+                write!(f, "charge held {}f, needs {}f", held, needed)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub move_id: MoveId,
+    pub reason: FailureReason,
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.move_id, self.reason)
+    }
+}
+
+// Physical attack buttons, named after their place on a gamepad
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttackButton {
+    North,
+    South,
+    East,
+    West,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+}
+
+pub const ATTACK_BUTTONS: [AttackButton; 8] = [
+    AttackButton::North,
+    AttackButton::South,
+    AttackButton::East,
+    AttackButton::West,
+    AttackButton::LeftShoulder,
+    AttackButton::RightShoulder,
+    AttackButton::LeftTrigger,
+    AttackButton::RightTrigger,
+];
+
+impl AttackButton {
+    pub fn name(self) -> &'static str {
+        match self {
+            AttackButton::North => "north",
+            AttackButton::South => "south",
+            AttackButton::East => "east",
+            AttackButton::West => "west",
+            AttackButton::LeftShoulder => "left_shoulder",
+            AttackButton::RightShoulder => "right_shoulder",
+            AttackButton::LeftTrigger => "left_trigger",
+            AttackButton::RightTrigger => "right_trigger",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AttackButton> {
+        ATTACK_BUTTONS
+            .into_iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Press,
+    Release,
+}
+
+// A button changing state, on the frame the change was seen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonEdge {
+    pub button: AttackButton,
+    pub edge: Edge,
+    pub frame: u64,
+}
+
+// Any button an input source can report, directions included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    Attack(AttackButton),
+}
+
+const DIRECTION_BUTTONS: [InputButton; 4] = [
+    InputButton::Up,
+    InputButton::Down,
+    InputButton::Left,
+    InputButton::Right,
+];
+
+impl InputButton {
+    pub fn name(self) -> &'static str {
+        match self {
+            InputButton::Up => "up",
+            InputButton::Down => "down",
+            InputButton::Left => "left",
+            InputButton::Right => "right",
+            InputButton::Attack(button) => button.name(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<InputButton> {
+        DIRECTION_BUTTONS
+            .into_iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+            .or_else(|| AttackButton::from_name(name).map(InputButton::Attack))
+    }
+}
+
+// What ends up in the direction history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryMode {
+    // The direction held at the end of each frame, as a game samples it
+    GameAccurate,
+    // Also every direction passed through and every button tapped between
+    // two frames
+    Lossless,
+}
+
+pub const HISTORY_MODES: [HistoryMode; 2] = [HistoryMode::GameAccurate, HistoryMode::Lossless];
+
+impl HistoryMode {
+    pub fn switched(self) -> HistoryMode {
+        match self {
+            HistoryMode::GameAccurate => HistoryMode::Lossless,
+            HistoryMode::Lossless => HistoryMode::GameAccurate,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HistoryMode::GameAccurate => "game",
+            HistoryMode::Lossless => "lossless",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HistoryMode> {
+        HISTORY_MODES
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// Length of a game frame at 60 frames per second
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_666_667);
+
+// Game frame an input timestamp falls in
+pub fn frame_of(time: Duration) -> u64 {
+    (time.as_nanos() / FRAME_DURATION.as_nanos()) as u64
+}
+
+// A button pressed and released within one game frame, which sampling the
+// buttons once per frame never sees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubFrameTap {
+    pub button: InputButton,
+    pub frame: u64,
+    pub held: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StickAxis {
+    X,
+    Y,
+}
+
+impl StickAxis {
+    pub fn name(self) -> &'static str {
+        match self {
+            StickAxis::X => "stick_x",
+            StickAxis::Y => "stick_y",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputChange {
+    Button(InputButton, ButtonState),
+    // Left stick axis moved, from -1.0 to 1.0 with up and right positive
+    Stick(StickAxis, f32),
+}
+
+// Backend neutral input change, `time` is counted from the start of the
+// input source and `device` numbers the source's devices in the order they
+// were first used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    pub time: Duration,
+    pub device: usize,
+    pub change: InputChange,
+}
+
+// Physical outline limiting the stick travel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateShape {
+    Square,
+    Octagon,
+    Circle,
+}
+
+pub const GATE_SHAPES: [GateShape; 3] = [GateShape::Square, GateShape::Octagon, GateShape::Circle];
+
+impl GateShape {
+    pub fn name(self) -> &'static str {
+        match self {
+            GateShape::Square => "square",
+            GateShape::Octagon => "octagon",
+            GateShape::Circle => "circle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<GateShape> {
+        GATE_SHAPES
+            .into_iter()
+            .find(|gate| gate.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// How raw stick positions become one of the eight directions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StickSettings {
+    // Fraction of the way to the gate edge the stick must travel
+    pub deadzone: f32,
+    // Angle in degrees covered by each diagonal, cardinals get the rest
+    pub diagonal_width: f32,
+    pub gate: GateShape,
+}
+
+impl Default for StickSettings {
+    fn default() -> Self {
+        StickSettings {
+            deadzone: 0.3,
+            diagonal_width: 45.0,
+            gate: GateShape::Circle,
+        }
+    }
+}
+
+// How opposite directions held together are resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocdMode {
+    // Both cancel out
+    Neutral,
+    // The direction pressed last wins
+    LastInput,
+    // The direction pressed first wins
+    FirstInput,
+    // Up wins over down, left and right cancel out
+    UpPriority,
+}
+
+pub const SOCD_MODES: [SocdMode; 4] = [
+    SocdMode::Neutral,
+    SocdMode::LastInput,
+    SocdMode::FirstInput,
+    SocdMode::UpPriority,
+];
+
+impl SocdMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            SocdMode::Neutral => "neutral",
+            SocdMode::LastInput => "last",
+            SocdMode::FirstInput => "first",
+            SocdMode::UpPriority => "up",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SocdMode> {
+        SOCD_MODES
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ButtonsStates {
     pub up: ButtonState,
     pub down: ButtonState,
     pub left: ButtonState,
     pub right: ButtonState,
-    pub attack_north: ButtonState,
-    pub attack_south: ButtonState,
-    pub attack_east: ButtonState,
-    pub attack_west: ButtonState,
+    // Indexed by `AttackButton`
+    pub attacks: [ButtonState; 8],
+    // Most recently pressed direction of each axis, for SOCD resolution
+    pub last_horizontal: Option<InputButton>,
+    pub last_vertical: Option<InputButton>,
+    // Raw left stick position
+    pub stick: (f32, f32),
+}
+
+impl ButtonsStates {
+    // Direction buttons currently held, in up, down, left, right order
+    pub fn held_directions(&self) -> Vec<InputButton> {
+        [
+            (InputButton::Up, self.up),
+            (InputButton::Down, self.down),
+            (InputButton::Left, self.left),
+            (InputButton::Right, self.right),
+        ]
+        .into_iter()
+        .filter(|(_, state)| *state == ButtonState::Pressed)
+        .map(|(button, _)| button)
+        .collect()
+    }
+
+    pub fn attack(&self, button: AttackButton) -> &ButtonState {
+        &self.attacks[button as usize]
+    }
+
+    pub fn button_mut(&mut self, button: InputButton) -> &mut ButtonState {
+        match button {
+            InputButton::Up => &mut self.up,
+            InputButton::Down => &mut self.down,
+            InputButton::Left => &mut self.left,
+            InputButton::Right => &mut self.right,
+            InputButton::Attack(attack) => self.attack_mut(attack),
+        }
+    }
+
+    pub fn attack_mut(&mut self, button: AttackButton) -> &mut ButtonState {
+        &mut self.attacks[button as usize]
+    }
+}
+
+// Where a replay is at, `frame` being the number of frames played
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayStatus {
+    pub frame: u64,
+    pub length: u64,
+    pub speed: f64,
+    pub playing: bool,
+}
+
+// How late the last game frames were processed compared to a perfect 60Hz
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TickJitter {
+    pub average: Duration,
+    pub max: Duration,
 }
 
 #[derive(Debug, Clone)]
 pub struct GlobalState {
+    // 1 for the first player
+    pub player: usize,
+    // Direction physically held, whatever the facing
     pub current_position: NumericalNotation,
+    pub facing: Facing,
+    // Directions held before SOCD cleaning turned them into `current_position`
+    pub held_directions: Vec<InputButton>,
+    pub socd_mode: SocdMode,
+    pub history_mode: HistoryMode,
+    pub stick: (f32, f32),
     pub attack_pressed: bool,
+    // Directions as if facing right, which moves are matched against
     pub position_history: Vec<NumericalNotation>,
-    pub close_requested: bool,
-    pub last_successful_move: Vec<(Moves, usize)>,
-}
-
-pub fn create_move_map() -> HashMap<Moves, Vec<NumericalNotation>> {
-    let mut move_map = HashMap::new();
-    move_map.insert(
-        Moves::DP,
-        vec![
-            NumericalNotation::Six,
-            NumericalNotation::Two,
-            NumericalNotation::Three,
-        ],
-    );
-    move_map.insert(
-        Moves::HCB,
-        vec![
-            NumericalNotation::Six,
-            NumericalNotation::Three,
-            NumericalNotation::Two,
-            NumericalNotation::One,
-            NumericalNotation::Four,
-        ],
-    );
-    move_map
+    // Directions as physically held
+    pub raw_history: Vec<NumericalNotation>,
+    // Input timestamp each history entry's direction started at
+    pub direction_times: Vec<Duration>,
+    // Frame each history entry was sampled on, counting only the frames
+    // played; lossless mode can give several entries the same frame
+    pub history_frames: Vec<u64>,
+    // Latest taps too short to be seen on any frame
+    pub sub_frame_taps: Vec<SubFrameTap>,
+    pub paused: bool,
+    pub last_successful_move: Vec<MatchResult>,
+    pub last_failed_attempt: Option<Diagnosis>,
+    // Message about the player's setup, e.g. the button profile in use
+    pub notice: Option<String>,
+    pub tick_jitter: TickJitter,
+    pub replay: Option<ReplayStatus>,
+}
+
+impl GlobalState {
+    pub fn new(player: usize, socd_mode: SocdMode) -> GlobalState {
+        GlobalState {
+            player,
+            current_position: NumericalNotation::Five,
+            facing: Facing::Right,
+            held_directions: Vec::new(),
+            socd_mode,
+            history_mode: HistoryMode::GameAccurate,
+            stick: (0.0, 0.0),
+            attack_pressed: false,
+            position_history: Vec::new(),
+            raw_history: Vec::new(),
+            direction_times: Vec::new(),
+            history_frames: Vec::new(),
+            sub_frame_taps: Vec::new(),
+            paused: false,
+            last_successful_move: vec![],
+            last_failed_attempt: None,
+            notice: None,
+            tick_jitter: TickJitter::default(),
+            replay: None,
+        }
+    }
 }
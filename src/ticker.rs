@@ -0,0 +1,87 @@
+use rust_input_speed::static_types::TickJitter;
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Ticks kept to measure jitter, one second of game frames
+const JITTER_WINDOW: usize = 60;
+// Sleeping is only accurate to about a millisecond, the end of each wait
+// yields instead
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+// Fixed rate clock for the game frames. Every tick is due at a multiple of
+// the period from the start, so a late wake up never delays the ticks after
+// it.
+pub struct Ticker {
+    started: Instant,
+    period: Duration,
+    frame: u64,
+    // How late each of the last ticks woke up
+    lateness: VecDeque<Duration>,
+}
+
+impl Ticker {
+    // Frame 0 ends a period after `started`. Live input passes the start of
+    // frame 0 plus `FRAME_DELAY`, so a frame is played once its late events
+    // came in.
+    pub fn new(period: Duration, started: Instant) -> Ticker {
+        Ticker {
+            started,
+            period,
+            frame: 0,
+            lateness: VecDeque::new(),
+        }
+    }
+
+    // Waits for the end of the current frame and returns its number, the
+    // first frame being 0
+    pub fn wait(&mut self) -> u64 {
+        let deadline = self.started + self.period * (self.frame + 1) as u32;
+        let now = Instant::now();
+        if deadline > now + SPIN_MARGIN {
+            thread::sleep(deadline - now - SPIN_MARGIN);
+        }
+        while Instant::now() < deadline {
+            thread::yield_now();
+        }
+
+        self.lateness
+            .push_back(Instant::now().duration_since(deadline));
+        if self.lateness.len() > JITTER_WINDOW {
+            self.lateness.pop_front();
+        }
+        self.frame += 1;
+        self.frame - 1
+    }
+
+    pub fn jitter(&self) -> TickJitter {
+        let Some(&max) = self.lateness.iter().max() else {
+            return TickJitter::default();
+        };
+        let total: Duration = self.lateness.iter().sum();
+        TickJitter {
+            average: total / self.lateness.len() as u32,
+            max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_stay_on_schedule() {
+        let period = Duration::from_millis(2);
+        let mut ticker = Ticker::new(period, Instant::now());
+        let started = ticker.started;
+
+        let frames: Vec<u64> = (0..5).map(|_| ticker.wait()).collect();
+
+        assert_eq!(frames, vec![0, 1, 2, 3, 4]);
+        assert!(started.elapsed() >= period * 5);
+        let jitter = ticker.jitter();
+        assert!(jitter.average <= jitter.max);
+        assert_eq!(ticker.lateness.len(), 5);
+    }
+}